@@ -0,0 +1,71 @@
+//! Workloads shared by the benchmarks.
+//!
+//! The language has no statements, functions, loops or classes yet,
+//! so the workloads are long expressions, in the shapes the compiler and VM can handle.
+//! `list`, `map` and `string` allocate on every run, so they also measure the heap and the collector.
+
+#![allow(dead_code)] // each bench uses a subset of these
+
+use rlox::chunk::Chunk;
+use rlox::compiler::compile_unfolded;
+
+/// A large synthetic script touching every kind of token.
+/// It is only meant to be scanned: it doesn't compile yet.
+pub fn synthetic_source(repeat: usize) -> String {
+    let unit = r#"
+// a line comment
+var counter_1 = 0x1F + 1_000 * 2.5e-3; /* a block
+   comment */
+fun fibonacci(n) {
+    if (n <= 1) return n;
+    return fibonacci(n - 2) + fibonacci(n - 1);
+}
+while (counter_1 < 100 and !false or nil == true) {
+    print "iteration \"quoted\" \u{41}" + counter_1;
+    counter_1 = counter_1 + 1;
+}
+"#;
+    unit.repeat(repeat)
+}
+
+/// Compile `src` without constant folding, so that the VM does the work,
+/// and drop the final `Return` so that running it prints nothing.
+pub fn compile_quiet(src: &str) -> Chunk {
+    let mut chunk = compile_unfolded(src).expect("benchmark source should compile");
+    chunk.code.pop();
+    chunk
+}
+
+/// Expressions which compile and run, by name.
+pub fn workloads() -> Vec<(&'static str, String)> {
+    // the constant pool holds 256 values at most.
+    let arithmetic = (1..250).fold("0".to_string(), |acc, i| match i % 4 {
+        0 => format!("({} + {})", acc, i),
+        1 => format!("({} - {})", acc, i),
+        2 => format!("({} * {})", acc, i),
+        _ => format!("({} / {})", acc, i),
+    });
+    // long chains of unary operators, split into groups below the compiler's nesting limit.
+    // spaced, as `--` is a decrement.
+    let negate = vec![format!("({}1)", "- ".repeat(200)); 50].join(" + ");
+    let not = vec![format!("({}nil)", "!".repeat(200)); 50].join(" == ");
+    let sum = (0..250).map(|i| i.to_string()).collect::<Vec<_>>().join(" + ");
+    let compare = vec!["!(true == (nil != false))"; 2_000].join(" == ");
+    // literals stay below the register machine's limit of 128 elements.
+    let list = format!("[{}]", vec!["[nil, true, false, nil].slice(1).len()"; 100].join(", "));
+    let map = (0..50).map(|i| format!("\"k{}\": [nil, nil, nil, nil]", i)).collect::<Vec<_>>().join(", ");
+    let map = format!("{{{}}}.values().len()", map);
+    // every concatenation interns a new, longer string.
+    let string = (0..250).fold("\"\"".to_string(), |acc, i| format!("({} + \"{}\")", acc, i % 10));
+
+    vec![
+        ("arithmetic", arithmetic),
+        ("sum", sum),
+        ("negate", negate),
+        ("not", not),
+        ("compare", compare),
+        ("list", list),
+        ("map", map),
+        ("string", string),
+    ]
+}
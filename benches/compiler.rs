@@ -0,0 +1,17 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rlox::compiler::compile;
+
+mod common;
+use common::workloads;
+
+fn compile_workloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compiler");
+    for (name, src) in workloads() {
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_function(name, |b| b.iter(|| compile(&src)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compile_workloads);
+criterion_main!(benches);
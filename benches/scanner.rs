@@ -0,0 +1,19 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rlox::scanner::Scanner;
+
+mod common;
+use common::synthetic_source;
+
+fn scan(c: &mut Criterion) {
+    let src = synthetic_source(2000);
+
+    let mut group = c.benchmark_group("scanner");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("synthetic", |b| {
+        b.iter(|| Scanner::from_source(&src).count())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, scan);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+//! Compare the `Value` representations:
+//! `cargo bench --bench value` and `cargo bench --bench value --features nan-boxing`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rlox::value::Value;
+
+fn values(n: usize) -> Vec<Value> {
+    (0..n).map(|i| match i % 8 {
+        0 => Value::from(()),
+        1 => Value::from(i % 3 == 0),
+        _ => Value::from(i as f64 * 0.5),
+    }).collect()
+}
+
+fn numbers(n: usize) -> Vec<Value> {
+    (0..n).map(|i| Value::from(i as f64)).collect()
+}
+
+fn value(c: &mut Criterion) {
+    let mut group = c.benchmark_group("value");
+
+    let nums = numbers(10_000);
+    group.bench_function("arithmetic", |b| {
+        b.iter(|| {
+            nums.iter().fold(Value::from(0.0), |acc, &v| {
+                let sq = v.checked_mul(v).unwrap();
+                acc.checked_add(sq).unwrap().checked_sub(v).unwrap()
+            })
+        })
+    });
+
+    group.bench_function("compare", |b| {
+        b.iter(|| {
+            nums.windows(2).filter(|w| bool::from(w[0].checked_lt(w[1]).unwrap())).count()
+        })
+    });
+
+    let mixed = values(10_000);
+    group.bench_function("truthiness", |b| {
+        b.iter(|| mixed.iter().filter(|&&v| bool::from(!v)).count())
+    });
+    group.bench_function("equality", |b| {
+        b.iter(|| mixed.windows(2).filter(|w| w[0] == w[1]).count())
+    });
+
+    group.bench_function("stack", |b| {
+        // push and pop through a Vec, like `VM::stack`
+        let mut stack = Vec::with_capacity(mixed.len());
+        b.iter(|| {
+            stack.extend_from_slice(black_box(&mixed));
+            let mut last = Value::from(());
+            while let Some(v) = stack.pop() {
+                last = v;
+            }
+            last
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, value);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rlox::optimizer::optimize;
+use rlox::register::compiler::translate;
+use rlox::register::vm::RegisterVM;
+use rlox::vm::VM;
+
+mod common;
+use common::{compile_quiet, workloads};
+
+fn run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm");
+    for (name, src) in workloads() {
+        let mut vm = VM::new(compile_quiet(&src));
+        group.bench_function(name, |b| b.iter(|| vm.run()));
+    }
+    group.finish();
+}
+
+/// The same workloads after the peephole pass, e.g. with superinstructions.
+fn run_optimized(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm-optimized");
+    for (name, src) in workloads() {
+        let mut chunk = compile_quiet(&src);
+        optimize(&mut chunk);
+        let mut vm = VM::new(chunk);
+        group.bench_function(name, |b| b.iter(|| vm.run()));
+    }
+    group.finish();
+}
+
+/// The same workloads on the register machine. The instruction counts of both
+/// are printed, as straight-line code executes each instruction exactly once.
+fn run_register(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vm-register");
+    for (name, src) in workloads() {
+        let chunk = compile_quiet(&src);
+        let Some(reg_chunk) = translate(&chunk) else {
+            eprintln!("{}: too deep for the register machine, skipped", name);
+            continue;
+        };
+        eprintln!("{}: {} stack instructions, {} register instructions",
+            name, chunk.iter().count(), reg_chunk.iter().count());
+
+        let mut vm = RegisterVM::new(reg_chunk);
+        group.bench_function(name, |b| b.iter(|| vm.run()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, run, run_optimized, run_register);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rlox::chunk::Chunk;
+
+fuzz_target!(|data: &[u8]| {
+    let mut chunk = Chunk::new();
+    chunk.code = data.to_vec();
+
+    // `next_instr_point` consumes at least one byte, and the instructions tile the code.
+    let mut expected_offset = 0;
+    for (_, offset) in chunk.iter() {
+        assert_eq!(offset, expected_offset);
+        let (_, len) = chunk.read(offset).unwrap();
+        assert!(len >= 1);
+        expected_offset += len;
+    }
+    assert_eq!(expected_offset, data.len());
+});
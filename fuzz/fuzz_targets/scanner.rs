@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rlox::scanner::Scanner;
+use rlox::token::Handler;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(src) = std::str::from_utf8(data) else { return };
+
+    let newlines = src.matches('\n').count();
+    let mut line = 1;
+    let mut eof_count = 0;
+
+    for token in Scanner::from_source(src) {
+        let token_line = match &token {
+            Ok(token) => token.line(),
+            Err(handler) => handler.line(),
+        };
+
+        // lines never go backwards, and never exceed the number of lines in the source.
+        assert!(line <= token_line && token_line <= newlines + 1);
+        line = token_line;
+
+        if let Err(Handler::EOF { .. }) = token {
+            eof_count += 1;
+        }
+    }
+
+    // the scanner emits EOF exactly once, as its last item.
+    assert_eq!(eof_count, 1);
+});
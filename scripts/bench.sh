@@ -0,0 +1,46 @@
+#!/bin/sh
+# Record or compare criterion baselines (stored under target/criterion).
+#
+#   scripts/bench.sh save [name] [bench args...]     record a baseline, `main` by default
+#   scripts/bench.sh compare [name] [bench args...]  compare against a recorded baseline,
+#                                                    and fail if anything regressed
+#
+# e.g. `git stash; scripts/bench.sh save; git stash pop; scripts/bench.sh compare`
+set -eu
+
+usage() {
+    echo "Usage: $0 save|compare [name] [bench args...]" >&2
+    exit 64
+}
+
+[ $# -ge 1 ] || usage
+cmd=$1
+shift
+name=${1:-main}
+[ $# -ge 1 ] && shift
+
+case "$cmd" in
+    save)
+        cargo bench "$@" -- --save-baseline "$name"
+        ;;
+    compare)
+        log=$(mktemp)
+        trap 'rm -f "$log"' EXIT
+        # changes within 5% are treated as noise.
+        # no pipe into tee here: plain sh has no pipefail, and a failed bench must not pass.
+        status=0
+        cargo bench "$@" -- --baseline "$name" --noise-threshold 0.05 >"$log" 2>&1 || status=$?
+        cat "$log"
+        if [ "$status" -ne 0 ]; then
+            echo "Benchmarks failed against baseline '$name' (exit $status)." >&2
+            exit "$status"
+        fi
+        if grep -q "Performance has regressed" "$log"; then
+            echo "Regressions found against baseline '$name'." >&2
+            exit 1
+        fi
+        ;;
+    *)
+        usage
+        ;;
+esac
@@ -1,4 +1,4 @@
-use crate::instr::{ InstrResult, ContextedInstrResult, OpPrefix, next_instr_point };
+use crate::instr::{ Instr, InstrResult, ContextedInstrResult, OpPrefix, next_instr_point };
 use crate::value::Value;
 
 use std::cmp::Ordering;
@@ -9,14 +9,21 @@ pub struct Chunk {
     pub code: Vec<u8>,
     line_begins: Vec<usize>,
     consts: Vec<Value>,
+    strings: Vec<Box<str>>, // string literals, interned into the VM's heap when loaded
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
-        Chunk { code: vec![], line_begins: vec![0], consts: vec![] }
+        Chunk { code: vec![], line_begins: vec![0], consts: vec![], strings: vec![] }
     }
 
-    pub fn iter(&self) -> CodeIterator {
+    pub fn iter(&self) -> CodeIterator<'_> {
         CodeIterator::new(&self.code)
     }
 
@@ -57,21 +64,81 @@ impl Chunk {
             .expect("const pool size should not exceed 256")
     }
 
+    /// Add a string literal, or find the same one added before.
+    pub fn add_string(&mut self, chars: &str) -> u8 {
+        let idx = self.strings.iter().position(|s| **s == *chars).unwrap_or_else(|| {
+            self.strings.push(chars.into());
+            self.strings.len() - 1
+        });
+        u8::try_from(idx).expect("string table size should not exceed 256")
+    }
+
     pub fn write_const(&mut self, value: Value, line: usize) {
         let c = self.add_const(value);
         self.write(OpPrefix::CONSTANT, line);
         self.write(c, line);
     }
 
-    pub fn get_const(&self, idx: u8) -> Value {
-        *self.consts.get(Into::<usize>::into(idx)).unwrap()
+    /// Write an already decoded instruction.
+    pub fn write_instr(&mut self, instr: Instr, line: usize) {
+        let (prefix, operands) = instr.encode();
+        self.write(prefix, line);
+        for byte in operands {
+            self.write(byte, line);
+        }
+    }
+
+    /// Remove all code, keeping the constant pool.
+    pub fn clear_code(&mut self) {
+        self.code.clear();
+        self.line_begins = vec![0];
+    }
+
+    /// Remove the code from `code_len` on, the constants from `const_count` on,
+    /// and the strings from `string_count` on.
+    /// The removed constants and strings must not be used by the remaining code.
+    pub fn truncate(&mut self, code_len: usize, const_count: usize, string_count: usize) {
+        self.code.truncate(code_len);
+        self.consts.truncate(const_count);
+        self.strings.truncate(string_count);
+
+        // drop the lines which have no code left, so that they can be written again.
+        while self.line_begins.len() > 1 && *self.line_begins.last().unwrap() >= code_len {
+            self.line_begins.pop();
+        }
+    }
+
+    pub fn get_const(&self, idx: u8) -> Option<Value> {
+        self.consts.get(usize::from(idx)).copied()
+    }
+
+    pub fn get_string(&self, idx: u8) -> Option<&str> {
+        self.strings.get(usize::from(idx)).map(|s| &**s)
+    }
+
+    /// Returns the source line of the instruction at `offset`.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.line_begins.partition_point(|&x| x <= offset).wrapping_sub(1)
+    }
+
+    /// Returns the line of the last written byte, i.e. the minimum line the next `write` accepts.
+    pub fn last_line(&self) -> usize {
+        self.line_begins.len() - 1
+    }
+
+    pub fn const_count(&self) -> usize {
+        self.consts.len()
+    }
+
+    pub fn string_count(&self) -> usize {
+        self.strings.len()
     }
 
     pub fn disasm(&self, ires: &InstrResult, offset: usize){
-        let line_no = self.line_begins.partition_point(|&x| x <= offset).wrapping_sub(1);
+        let line_no = self.line_of(offset);
         println!("{:04} {:4} {}",
             offset, line_no,
-            ContextedInstrResult::new(ires, &self.consts)
+            ContextedInstrResult::new(ires, self)
         );
     }
 
@@ -81,10 +148,10 @@ impl Chunk {
         let mut prev_line_no = usize::MAX;
 
         for (ires, offset) in self.iter() {
-            let line_no = self.line_begins.partition_point(|&x| x <= offset).wrapping_sub(1);
+            let line_no = self.line_of(offset);
             
             let line = if prev_line_no == line_no {
-                format!("   |")
+                "   |".to_string()
             } else {
                 prev_line_no = line_no;
                 format!("{:4}", line_no)
@@ -92,7 +159,7 @@ impl Chunk {
 
             println!("{:04} {} {}",
                 offset, line,
-                ContextedInstrResult::new(&ires, &self.consts)
+                ContextedInstrResult::new(&ires, self)
             );
         }
     }
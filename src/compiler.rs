@@ -1,30 +1,768 @@
 use crate::scanner::Scanner;
 use crate::chunk::Chunk;
-use crate::token::{Token, TokenResult};
+use crate::instr::OpPrefix;
+use crate::native::Method;
+use crate::value::{Value, ValueOpnResult};
+use crate::token::{Token, TokenType, Literal, TokenResult, Handler};
 
 pub fn compile(src: &str) -> Option<Chunk>{
-    let chunk = Chunk::new();
+    compile_with(src, true)
+}
+
+/// Compile `src` without constant folding, leaving every operation to the VM.
+pub fn compile_unfolded(src: &str) -> Option<Chunk> {
+    compile_with(src, false)
+}
 
+fn compile_with(src: &str, fold: bool) -> Option<Chunk> {
     let scanner = Scanner::from_source(src);
-    let parser = Parser::from_scanner(scanner);
+    let mut parser = Parser::from_scanner(scanner);
+    parser.fold = fold;
+
+    parser.advance();
+    parser.expression();
+    parser.consume_eof("Expect end of expression.");
+
+    parser.end()
+}
+
+/// How deeply expressions may nest, e.g. `((1))` is nested twice inside the whole expression.
+/// Parsing recurses at every level, so this keeps deep nesting from overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Precedences from lowest to highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    None,
+    Assignment, // =
+    Conditional, // ?:
+    Coalesce,   // ??
+    Or,         // or
+    And,        // and
+    Equality,   // == !=
+    Comparison, // < > <= >=
+    Term,       // + -
+    Factor,     // * /
+    Unary,      // ! - ++ --
+    Power,      // **
+    Call,       // . ?. () []
+    Primary,
+}
+
+impl Precedence {
+    /// One level higher than `self`. Used for left-associative binary operators.
+    fn next(self) -> Self {
+        match self {
+            Self::None => Self::Assignment,
+            Self::Assignment => Self::Conditional,
+            Self::Conditional => Self::Coalesce,
+            Self::Coalesce => Self::Or,
+            Self::Or => Self::And,
+            Self::And => Self::Equality,
+            Self::Equality => Self::Comparison,
+            Self::Comparison => Self::Term,
+            Self::Term => Self::Factor,
+            Self::Factor => Self::Unary,
+            Self::Unary => Self::Power,
+            Self::Power => Self::Call,
+            Self::Call | Self::Primary => Self::Primary,
+        }
+    }
+}
+
+/// `can_assign` is whether the expression may be the target of an `=`.
+type ParseFn<'a> = fn(&mut Parser<'a>, bool);
+
+struct ParseRule<'a> {
+    prefix: Option<ParseFn<'a>>,
+    infix: Option<ParseFn<'a>>,
+    precedence: Precedence,
+}
+
+impl<'a> ParseRule<'a> {
+    fn new(prefix: Option<ParseFn<'a>>, infix: Option<ParseFn<'a>>, precedence: Precedence) -> Self {
+        Self { prefix, infix, precedence }
+    }
+}
 
-    // parser.advance();
-    // parser.expression();
-    // parser.consume(Err(TokenResult::EOF), "Expect end of expression.");
+/// The value of a compile-time constant expression, and where its code begins.
+#[derive(Clone, Copy)]
+struct Constant {
+    value: Value,
+    code_len: usize,
+    const_count: usize,
+}
 
-    Some(chunk)
+/// A string literal, which is loaded from the chunk's string table rather than the constant pool.
+#[derive(Clone)]
+struct StringConstant {
+    chars: String,
+    code_len: usize,
+    const_count: usize,
+    string_count: usize,
 }
 
-pub struct Parser {
-    cur: TokenResult,
-    prev: TokenResult,
+pub struct Parser<'a> {
+    scanner: Scanner<'a>,
+    chunk: Chunk,
+
+    fold: bool,
+    // the expression compiled last, if it is a single load of a constant.
+    constant: Option<Constant>,
+    // the expression compiled last, if it is a single load of a string literal.
+    string: Option<StringConstant>,
+    // the offset of the `IndexGet` emitted last, if nothing was emitted after it.
+    // `++` and `--` turn it into an update of the same element.
+    index_get: Option<usize>,
+    // the number of `parse_precedence` calls in progress.
+    depth: usize,
+
+    cur: TokenResult<'a>,
+    prev: TokenResult<'a>,
     had_error: bool,
     panic_mode: bool,
 }
 
-impl Parser {
-    pub fn from_scanner(scanner: Scanner) -> Self {
-        todo!()
+impl<'a> Parser<'a> {
+    pub fn from_scanner(scanner: Scanner<'a>) -> Self {
+        Parser {
+            scanner,
+            chunk: Chunk::new(),
+            fold: true,
+            constant: None,
+            string: None,
+            index_get: None,
+            depth: 0,
+            cur: Err(Handler::eof(1)),
+            prev: Err(Handler::eof(1)),
+            had_error: false,
+            panic_mode: false,
+        }
+    }
+
+    // token handling
+
+    fn advance(&mut self) {
+        self.prev = std::mem::replace(&mut self.cur, Err(Handler::eof(0)));
+
+        loop {
+            // the scanner emits EOF once, and then None forever.
+            let line = self.prev.as_ref().map_or_else(Handler::line, |token| token.line());
+            self.cur = self.scanner.next().unwrap_or(Err(Handler::eof(line)));
+
+            if let Err(Handler::Error { message, line }) = self.cur {
+                self.error_at_current(message, line);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Returns the type of the current token, or `None` on EOF.
+    fn cur_type(&self) -> Option<TokenType> {
+        self.cur.as_ref().ok().map(|token| token.typ())
+    }
+
+    fn prev_type(&self) -> Option<TokenType> {
+        self.prev.as_ref().ok().map(|token| token.typ())
+    }
+
+    fn consume(&mut self, typ: TokenType, message: &str) {
+        if self.cur_type() == Some(typ) {
+            self.advance();
+        } else {
+            self.error_at(true, message);
+        }
+    }
+
+    /// Consume the current token if it is of type `typ`, and returns whether it was.
+    fn match_token(&mut self, typ: TokenType) -> bool {
+        if self.cur_type() == Some(typ) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume_eof(&mut self, message: &str) {
+        if !matches!(self.cur, Err(Handler::EOF { .. })) {
+            self.error_at(true, message);
+        }
+    }
+
+    // error reporting
+
+    fn error_at_current(&mut self, message: &str, line: usize) {
+        if self.panic_mode { return; }
+        self.panic_mode = true;
+        self.had_error = true;
+
+        // scanner errors have no lexeme to point at.
+        eprintln!("[line {}] Error: {}", line, message);
+    }
+
+    /// Report an error at the current token if `at_current`, otherwise at the previous token.
+    fn error_at(&mut self, at_current: bool, message: &str) {
+        if self.panic_mode { return; }
+        self.panic_mode = true;
+        self.had_error = true;
+
+        let token = if at_current { &self.cur } else { &self.prev };
+        match token {
+            Ok(token) => eprintln!("[line {}] Error at '{}': {}", token.line(), token.lexeme(), message),
+            Err(Handler::EOF { line }) => eprintln!("[line {}] Error at end: {}", line, message),
+            Err(Handler::Error { line, .. }) => eprintln!("[line {}] Error: {}", line, message),
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(false, message);
+    }
+
+    /// Report an error at the `}` which begins the current token, if it is the rest of an interpolated string.
+    /// Returns whether it was reported.
+    fn error_at_closing_brace(&mut self, message: &str) -> bool {
+        let line = match &self.cur {
+            Ok(token) if matches!(token.typ(), TokenType::String | TokenType::Interpolation)
+                && token.lexeme().starts_with('}') => token.line(),
+            _ => return false,
+        };
+        if !self.panic_mode {
+            self.panic_mode = true;
+            self.had_error = true;
+            eprintln!("[line {}] Error at '}}': {}", line, message);
+        }
+        true
+    }
+
+    // code emission
+
+    fn line(&self) -> usize {
+        let line = self.prev.as_ref().map_or_else(Handler::line, |token| token.line());
+
+        // `Chunk` only accepts monotonically increasing lines, but an operator
+        // is emitted after its right operand, which may span later lines.
+        line.max(self.chunk.last_line())
+    }
+
+    fn emit<B: Into<u8>>(&mut self, byte: B) {
+        self.constant = None;
+        self.string = None;
+        self.index_get = None;
+        let line = self.line();
+        self.chunk.write(byte, line);
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        if self.chunk.const_count() > u8::MAX.into() {
+            self.error("Too many constants in one chunk.");
+            return;
+        }
+        let line = self.line();
+        self.chunk.write_const(value, line);
+    }
+
+    /// Emit the load of a literal value, and remember it for constant folding.
+    fn emit_value(&mut self, value: Value) {
+        let (code_len, const_count) = (self.chunk.code.len(), self.chunk.const_count());
+
+        if value.is_nil() {
+            self.emit(OpPrefix::NIL);
+        } else if value == Value::from(true) {
+            self.emit(OpPrefix::TRUE);
+        } else if value == Value::from(false) {
+            self.emit(OpPrefix::FALSE);
+        } else {
+            self.emit_constant(value);
+        }
+
+        if self.fold {
+            self.constant = Some(Constant { value, code_len, const_count });
+        }
+    }
+
+    /// Emit a forward jump, and returns the offset of its operand, to be patched by `patch_jump`.
+    fn emit_jump(&mut self, op: OpPrefix) -> usize {
+        self.emit(op);
+        self.emit(0xFFu8);
+        self.emit(0xFFu8);
+        self.chunk.code.len() - 2
+    }
+
+    /// Point the jump whose operand is at `offset` to the code emitted next.
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 for the operand itself, as the jump is taken from the end of the instruction.
+        let Ok(jump) = u16::try_from(self.chunk.code.len() - offset - 2) else {
+            self.error("Too much code to jump over.");
+            return;
+        };
+        self.chunk.code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+
+        // the code emitted next is also reached by the jump, so it doesn't continue
+        // the last expression anymore, for constant folding and `++`.
+        self.constant = None;
+        self.string = None;
+        self.index_get = None;
+    }
+
+    /// Replace the code from the constant operand `from` on with the load of `result`.
+    /// An operation which fails is not folded, so that the VM reports the error at runtime.
+    /// Returns whether it was folded.
+    fn fold(&mut self, from: Constant, result: ValueOpnResult) -> bool {
+        let Ok(value) = result else { return false };
+        self.chunk.truncate(from.code_len, from.const_count, self.chunk.string_count());
+        self.emit_value(value);
+        true
+    }
+
+    fn end(mut self) -> Option<Chunk> {
+        self.emit(OpPrefix::RETURN);
+
+        #[cfg(feature = "debug-print-code")]
+        if !self.had_error {
+            self.chunk.disasm_all("code");
+        }
+
+        if self.had_error { None } else { Some(self.chunk) }
+    }
+
+    // expressions
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        if self.depth == MAX_DEPTH {
+            self.error_at(true, "Expression nesting too deep.");
+            return;
+        }
+        self.depth += 1;
+        self.parse_precedence_nested(precedence);
+        self.depth -= 1;
+    }
+
+    fn parse_precedence_nested(&mut self, precedence: Precedence) {
+        self.advance();
+
+        let Some(prefix) = self.prev_type().and_then(|typ| Self::rule(typ).prefix) else {
+            self.error("Expect expression.");
+            return;
+        };
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix(self, can_assign);
+
+        while let Some(typ) = self.cur_type() {
+            if precedence > Self::rule(typ).precedence {
+                break;
+            }
+            self.advance();
+            if let Some(infix) = Self::rule(typ).infix {
+                infix(self, can_assign);
+            }
+        }
+
+        let assignment = self.cur_type().is_some_and(|typ| typ == TokenType::Eq || Self::compound_op(typ).is_some());
+        if can_assign && assignment {
+            self.advance();
+            self.error("Invalid assignment target.");
+        }
+    }
+
+    /// The operation of a compound assignment operator, like `Add` for `+=`.
+    fn compound_op(typ: TokenType) -> Option<OpPrefix> {
+        match typ {
+            TokenType::PlusEq => Some(OpPrefix::ADD),
+            TokenType::MinusEq => Some(OpPrefix::SUBTRACT),
+            TokenType::StarEq => Some(OpPrefix::MULTIPLY),
+            TokenType::SlashEq => Some(OpPrefix::DIVIDE),
+            TokenType::PercentEq => Some(OpPrefix::MODULO),
+            _ => None,
+        }
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        let value = match self.prev.as_ref().map(Token::literal) {
+            Ok(Some(Literal::Number(num))) => *num,
+            _ => unreachable!(),
+        };
+        self.emit_value(Value::from(value));
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        match self.prev_type() {
+            Some(TokenType::False) => self.emit_value(Value::from(false)),
+            Some(TokenType::Nil) => self.emit_value(Value::from(())),
+            Some(TokenType::True) => self.emit_value(Value::from(true)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RParen, "Expect ')' after expression.");
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        let chars = match self.prev.as_ref().map(Token::literal) {
+            Ok(Some(Literal::String(chars))) => chars.clone(),
+            _ => unreachable!(),
+        };
+        self.emit_string(&chars);
+    }
+
+    /// Emit the load of a string literal, and remember it for folding concatenations.
+    fn emit_string(&mut self, chars: &str) {
+        if self.chunk.string_count() > u8::MAX.into() {
+            self.error("Too many strings in one chunk.");
+            return;
+        }
+        let (code_len, const_count, string_count) =
+            (self.chunk.code.len(), self.chunk.const_count(), self.chunk.string_count());
+
+        let idx = self.chunk.add_string(chars);
+        self.emit(OpPrefix::STRING);
+        self.emit(idx);
+
+        if self.fold {
+            self.string = Some(StringConstant { chars: chars.to_string(), code_len, const_count, string_count });
+        }
+    }
+
+    /// Emit the string part just consumed, unless it is empty. Returns the number of pushed values.
+    fn string_part(&mut self) -> usize {
+        match self.prev.as_ref().map(Token::literal) {
+            Ok(Some(Literal::String(chars))) if !chars.is_empty() => {
+                let chars = chars.clone();
+                self.emit_string(&chars);
+                1
+            },
+            _ => 0,
+        }
+    }
+
+    /// `"a ${b} c"`, scanned as `Interpolation("a ")`, the tokens of `b`, and `String(" c")`.
+    /// Each part is pushed, and then concatenated with the `Display` forms of the values.
+    fn interpolation(&mut self, _can_assign: bool) {
+        let mut count: usize = 0;
+        loop {
+            count += self.string_part();
+            // in `${}`, the rest of the string would be parsed as the expression.
+            if !self.error_at_closing_brace("Expect expression.") {
+                self.expression();
+                count += 1;
+            }
+
+            if !self.match_token(TokenType::Interpolation) {
+                break;
+            }
+        }
+        self.consume(TokenType::String, "Expect '}' after interpolated expression.");
+        count += self.string_part();
+
+        if count > u8::MAX.into() {
+            self.error("Too many parts in a string interpolation.");
+            return;
+        }
+        self.emit(OpPrefix::BUILD_STRING);
+        self.emit(count as u8);
+    }
+
+    /// `{key: value, ...}`. There are no blocks yet, so a `{` which begins an expression is always a map.
+    fn map(&mut self, _can_assign: bool) {
+        let mut count: usize = 0;
+        if self.cur_type() != Some(TokenType::RBrace) {
+            loop {
+                self.expression();
+                self.consume(TokenType::Colon, "Expect ':' after map key.");
+                self.expression();
+                if count == u8::MAX.into() {
+                    self.error("Can't have more than 255 entries in a map.");
+                }
+                count += 1;
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RBrace, "Expect '}' after map entries.");
+
+        self.emit(OpPrefix::BUILD_MAP);
+        self.emit(count as u8);
+    }
+
+    /// `[a, b, c]`
+    fn list(&mut self, _can_assign: bool) {
+        let mut count: usize = 0;
+        if self.cur_type() != Some(TokenType::RBracket) {
+            loop {
+                self.expression();
+                if count == u8::MAX.into() {
+                    self.error("Can't have more than 255 elements in a list.");
+                }
+                count += 1;
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RBracket, "Expect ']' after list elements.");
+
+        self.emit(OpPrefix::BUILD_LIST);
+        self.emit(count as u8);
+    }
+
+    /// `target[index]`, `target[index] = value`, or a compound assignment like `target[index] += value`.
+    /// `target` and `index` are evaluated only once.
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RBracket, "Expect ']' after index.");
+
+        let compound = self.cur_type().and_then(Self::compound_op);
+        if can_assign && self.match_token(TokenType::Eq) {
+            self.expression();
+            self.emit(OpPrefix::INDEX_SET);
+        } else if let (true, Some(op)) = (can_assign, compound) {
+            self.advance();
+            self.emit(OpPrefix::INDEX_PEEK);
+            self.expression();
+            self.emit(op);
+            self.emit(OpPrefix::INDEX_SET);
+        } else {
+            self.emit(OpPrefix::INDEX_GET);
+            self.index_get = Some(self.chunk.code.len() - 1);
+        }
+    }
+
+    /// `++target[index]` and `--target[index]`, which evaluate to the new value.
+    fn prefix_increment(&mut self, _can_assign: bool) {
+        let op = self.prev_type();
+        self.parse_precedence(Precedence::Unary);
+
+        let Some(offset) = self.index_get.filter(|&offset| offset + 1 == self.chunk.code.len()) else {
+            self.error("Invalid increment target.");
+            return;
+        };
+        self.chunk.code[offset] = OpPrefix::INDEX_PEEK.into();
+        self.emit_constant(Value::from(1.0));
+        match op {
+            Some(TokenType::PlusPlus) => self.emit(OpPrefix::ADD),
+            Some(TokenType::MinusMinus) => self.emit(OpPrefix::SUBTRACT),
+            _ => unreachable!(),
+        }
+        self.emit(OpPrefix::INDEX_SET);
+    }
+
+    /// `target[index]++` and `target[index]--`, which evaluate to the old value.
+    fn postfix_increment(&mut self, _can_assign: bool) {
+        let delta = match self.prev_type() {
+            Some(TokenType::PlusPlus) => 1.0,
+            Some(TokenType::MinusMinus) => -1.0,
+            _ => unreachable!(),
+        };
+
+        let Some(offset) = self.index_get.filter(|&offset| offset + 1 == self.chunk.code.len()) else {
+            self.error("Invalid increment target.");
+            return;
+        };
+        // the target and index stay on the stack, for `IndexPostAdd` to update.
+        self.chunk.truncate(offset, self.chunk.const_count(), self.chunk.string_count());
+        self.emit_constant(Value::from(delta));
+        self.emit(OpPrefix::INDEX_POST_ADD);
+    }
+
+    /// `receiver.name(args)`, where `name` is a native method.
+    fn dot(&mut self, _can_assign: bool) {
+        self.consume(TokenType::Ident, "Expect method name after '.'.");
+        let method = match &self.prev {
+            Ok(token) if token.typ() == TokenType::Ident => Method::from_name(token.lexeme()),
+            _ => return,
+        };
+        let Some(method) = method else {
+            self.error("Unknown method.");
+            return;
+        };
+
+        self.consume(TokenType::LParen, "Expect '(' after method name.");
+        let argc = self.argument_list();
+
+        self.emit(OpPrefix::INVOKE);
+        self.emit(method);
+        self.emit(argc);
+    }
+
+    /// `receiver?.name(args)`, which evaluates to `nil` without evaluating the arguments if `receiver` is `nil`.
+    /// Each `?.` only guards its own call, so a chain like `a?.b()?.c()` needs one for every link.
+    fn optional_dot(&mut self, can_assign: bool) {
+        let end_jump = self.emit_jump(OpPrefix::JUMP_IF_NIL);
+        self.dot(can_assign);
+        self.patch_jump(end_jump);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut argc: usize = 0;
+        if self.cur_type() != Some(TokenType::RParen) {
+            loop {
+                self.expression();
+                if argc == u8::MAX.into() {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                argc += 1;
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RParen, "Expect ')' after arguments.");
+        argc as u8
+    }
+
+    /// `condition ? then : else`. Like in C, `then` may be any expression,
+    /// and `else` is right-associative, so `a ? b : c ? d : e` is `a ? b : (c ? d : e)`.
+    fn conditional(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpPrefix::JUMP_IF_FALSE);
+        self.emit(OpPrefix::POP);
+        self.expression();
+        self.consume(TokenType::Colon, "Expect ':' after then branch of conditional expression.");
+        let end_jump = self.emit_jump(OpPrefix::JUMP);
+
+        self.patch_jump(else_jump);
+        self.emit(OpPrefix::POP);
+        self.parse_precedence(Precedence::Conditional);
+        self.patch_jump(end_jump);
+    }
+
+    /// `value ?? default`, which evaluates `default` only if `value` is `nil`. Right-associative.
+    fn coalesce(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(OpPrefix::JUMP_IF_NOT_NIL);
+        self.emit(OpPrefix::POP);
+        self.parse_precedence(Precedence::Coalesce);
+        self.patch_jump(end_jump);
+    }
+
+    /// `throw value`. Nothing can catch it yet, so it ends the script with a runtime error.
+    /// As an expression, it takes everything to its right as the thrown value.
+    fn throw(&mut self, _can_assign: bool) {
+        self.parse_precedence(Precedence::Assignment);
+        self.emit(OpPrefix::THROW);
+    }
+
+    /// `break` and `continue`. There are no loops yet, so they are always misplaced.
+    fn loop_jump(&mut self, _can_assign: bool) {
+        match self.prev_type() {
+            Some(TokenType::Break) => self.error("Can't use 'break' outside of a loop."),
+            Some(TokenType::Continue) => self.error("Can't use 'continue' outside of a loop."),
+            _ => unreachable!(),
+        }
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let op = self.prev_type();
+
+        // compile the operand
+        self.parse_precedence(Precedence::Unary);
+
+        if let Some(operand) = self.constant {
+            let result = match op {
+                Some(TokenType::Bang) => Ok(!operand.value),
+                Some(TokenType::Minus) => operand.value.checked_neg(),
+                _ => unreachable!(),
+            };
+            if self.fold(operand, result) {
+                return;
+            }
+        }
+
+        match op {
+            Some(TokenType::Bang) => self.emit(OpPrefix::NOT),
+            Some(TokenType::Minus) => self.emit(OpPrefix::NEGATE),
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let op = self.prev_type().unwrap();
+        let lhs = self.constant;
+        let lhs_string = self.string.take();
+
+        // compile the right operand. `**` is right-associative.
+        let precedence = Self::rule(op).precedence;
+        self.parse_precedence(if op == TokenType::StarStar { precedence } else { precedence.next() });
+
+        if let (Some(lhs), Some(rhs)) = (lhs, self.constant) {
+            let (a, b) = (lhs.value, rhs.value);
+            let result = match op {
+                // comparing values of different types depends on the VM's strict mode.
+                TokenType::BangEq => a.checked_eq(b).map(|eq| !eq),
+                TokenType::EqEq => a.checked_eq(b),
+                TokenType::Gt => a.checked_gt(b),
+                TokenType::GtEq => a.checked_ge(b),
+                TokenType::Lt => a.checked_lt(b),
+                TokenType::LtEq => a.checked_le(b),
+                TokenType::Plus => a.checked_add(b),
+                TokenType::Minus => a.checked_sub(b),
+                TokenType::Star => a.checked_mul(b),
+                TokenType::Slash => a.checked_div(b),
+                TokenType::Percent => a.checked_rem(b),
+                TokenType::StarStar => a.checked_pow(b),
+                _ => unreachable!(),
+            };
+            if self.fold(lhs, result) {
+                return;
+            }
+        }
+        if let (TokenType::Plus, Some(lhs), Some(rhs)) = (op, lhs_string, &self.string) {
+            let chars = lhs.chars + &rhs.chars;
+            self.chunk.truncate(lhs.code_len, lhs.const_count, lhs.string_count);
+            self.emit_string(&chars);
+            return;
+        }
+
+        match op {
+            TokenType::BangEq => { self.emit(OpPrefix::EQUAL); self.emit(OpPrefix::NOT); },
+            TokenType::EqEq => self.emit(OpPrefix::EQUAL),
+            TokenType::Gt => self.emit(OpPrefix::GREATER),
+            TokenType::GtEq => self.emit(OpPrefix::GREATER_EQUAL),
+            TokenType::Lt => self.emit(OpPrefix::LESS),
+            TokenType::LtEq => self.emit(OpPrefix::LESS_EQUAL),
+            TokenType::Plus => self.emit(OpPrefix::ADD),
+            TokenType::Minus => self.emit(OpPrefix::SUBTRACT),
+            TokenType::Star => self.emit(OpPrefix::MULTIPLY),
+            TokenType::Slash => self.emit(OpPrefix::DIVIDE),
+            TokenType::Percent => self.emit(OpPrefix::MODULO),
+            TokenType::StarStar => self.emit(OpPrefix::POWER),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The Pratt parser table.
+    fn rule(typ: TokenType) -> ParseRule<'a> {
+        use TokenType::*;
+        match typ {
+            LParen => ParseRule::new(Some(Self::grouping), None, Precedence::None),
+            LBracket => ParseRule::new(Some(Self::list), Some(Self::index), Precedence::Call),
+            LBrace => ParseRule::new(Some(Self::map), None, Precedence::None),
+            Dot => ParseRule::new(None, Some(Self::dot), Precedence::Call),
+            QuestionDot => ParseRule::new(None, Some(Self::optional_dot), Precedence::Call),
+            Question => ParseRule::new(None, Some(Self::conditional), Precedence::Conditional),
+            QuestionQuestion => ParseRule::new(None, Some(Self::coalesce), Precedence::Coalesce),
+            Minus => ParseRule::new(Some(Self::unary), Some(Self::binary), Precedence::Term),
+            Plus => ParseRule::new(None, Some(Self::binary), Precedence::Term),
+            Slash | Star | Percent => ParseRule::new(None, Some(Self::binary), Precedence::Factor),
+            StarStar => ParseRule::new(None, Some(Self::binary), Precedence::Power),
+            PlusPlus | MinusMinus => ParseRule::new(Some(Self::prefix_increment), Some(Self::postfix_increment), Precedence::Call),
+            Bang => ParseRule::new(Some(Self::unary), None, Precedence::None),
+            BangEq | EqEq => ParseRule::new(None, Some(Self::binary), Precedence::Equality),
+            Gt | GtEq | Lt | LtEq => ParseRule::new(None, Some(Self::binary), Precedence::Comparison),
+            Number => ParseRule::new(Some(Self::number), None, Precedence::None),
+            String => ParseRule::new(Some(Self::string), None, Precedence::None),
+            Interpolation => ParseRule::new(Some(Self::interpolation), None, Precedence::None),
+            False | Nil | True => ParseRule::new(Some(Self::literal), None, Precedence::None),
+            Throw => ParseRule::new(Some(Self::throw), None, Precedence::None),
+            Break | Continue => ParseRule::new(Some(Self::loop_jump), None, Precedence::None),
+            _ => ParseRule::new(None, None, Precedence::None),
+        }
     }
 }
 
@@ -35,4 +773,4 @@ impl Parser {
 // Nystrom do this because it keeps our compiler simpler.
 
 // single-pass compilers don't work well for all languages
-// fortunately, tiny, dynamically typed Lox is well-suited to that (He did design the language specifically for this book)
\ No newline at end of file
+// fortunately, tiny, dynamically typed Lox is well-suited to that (He did design the language specifically for this book)
@@ -1,4 +1,5 @@
-use crate::value::Value;
+use crate::chunk::Chunk;
+use crate::native::Method;
 use std::fmt;
 use num_enum::{ FromPrimitive, IntoPrimitive };
 
@@ -6,17 +7,46 @@ use num_enum::{ FromPrimitive, IntoPrimitive };
 /// 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
+#[allow(non_camel_case_types)] // named after clox's OP_XXX
 pub enum OpPrefix {
     CONSTANT = 0,
     NIL,
     TRUE,
     FALSE,
+    EQUAL,
+    GREATER,
+    LESS,
+    NOT_EQUAL,
+    GREATER_EQUAL,
+    LESS_EQUAL,
     ADD,
     SUBTRACT,
     MULTIPLY,
     DIVIDE,
+    NOT,
     NEGATE,
     RETURN,
+    ADD_CONST,
+    CONSTANT_0,
+    CONSTANT_1,
+    RETURN_NIL,
+    BUILD_LIST,
+    INDEX_GET,
+    INDEX_SET,
+    INVOKE,
+    STRING,
+    BUILD_MAP,
+    THROW,
+    BUILD_STRING,
+    MODULO,
+    POWER,
+    INDEX_PEEK,
+    INDEX_POST_ADD,
+    POP,
+    JUMP,
+    JUMP_IF_FALSE,
+    JUMP_IF_NIL,
+    JUMP_IF_NOT_NIL,
     #[num_enum(catch_all)]
     UNKNOWN(u8),
 }
@@ -36,14 +66,115 @@ pub enum Instr {
     Equal,
     Greater,
     Less,
+    NotEqual, // only emitted by the optimizer, for `Equal; Not`
+    GreaterEqual,
+    LessEqual,
     Add,
     Subtract,
     Multiply,
     Divide,
+    Not,
     Negate,
     Return,
+
+    // superinstructions, only emitted by the optimizer
+    AddConst{ idx: u8 }, // `Constant { idx }; Add`
+    Constant0, // `Constant { idx: 0 }`
+    Constant1, // `Constant { idx: 1 }`
+    ReturnNil, // `Nil; Return`
+
+    BuildList{ count: u8 },
+    IndexGet,
+    IndexSet,
+    Invoke{ method: Method, argc: u8 },
+    String{ idx: u8 }, // index into the chunk's string literals
+    BuildMap{ count: u8 }, // of key-value pairs
+    Throw,
+    BuildString{ count: u8 }, // of parts, concatenated in their `Display` forms
+    Modulo,
+    Power,
+    IndexPeek, // `IndexGet`, keeping the target and index on the stack for a following `IndexSet`
+    IndexPostAdd, // `target[index] += delta`, evaluating to the old value
+    Pop,
+
+    // forward jumps, by `offset` bytes from the end of the instruction.
+    // the conditional ones test the top of the stack without popping it.
+    Jump{ offset: u16 },
+    JumpIfFalse{ offset: u16 },
+    JumpIfNil{ offset: u16 },
+    JumpIfNotNil{ offset: u16 },
+}
+
+impl Instr {
+    /// Returns the opcode, and the operand bytes of the instruction.
+    /// This is the inverse of `next_instr_point`.
+    pub fn encode(&self) -> (OpPrefix, Vec<u8>) {
+        match *self {
+            Instr::Constant { idx } => (OpPrefix::CONSTANT, vec![idx]),
+            Instr::Nil => (OpPrefix::NIL, vec![]),
+            Instr::True => (OpPrefix::TRUE, vec![]),
+            Instr::False => (OpPrefix::FALSE, vec![]),
+            Instr::Equal => (OpPrefix::EQUAL, vec![]),
+            Instr::Greater => (OpPrefix::GREATER, vec![]),
+            Instr::Less => (OpPrefix::LESS, vec![]),
+            Instr::NotEqual => (OpPrefix::NOT_EQUAL, vec![]),
+            Instr::GreaterEqual => (OpPrefix::GREATER_EQUAL, vec![]),
+            Instr::LessEqual => (OpPrefix::LESS_EQUAL, vec![]),
+            Instr::Add => (OpPrefix::ADD, vec![]),
+            Instr::Subtract => (OpPrefix::SUBTRACT, vec![]),
+            Instr::Multiply => (OpPrefix::MULTIPLY, vec![]),
+            Instr::Divide => (OpPrefix::DIVIDE, vec![]),
+            Instr::Not => (OpPrefix::NOT, vec![]),
+            Instr::Negate => (OpPrefix::NEGATE, vec![]),
+            Instr::Return => (OpPrefix::RETURN, vec![]),
+            Instr::AddConst { idx } => (OpPrefix::ADD_CONST, vec![idx]),
+            Instr::Constant0 => (OpPrefix::CONSTANT_0, vec![]),
+            Instr::Constant1 => (OpPrefix::CONSTANT_1, vec![]),
+            Instr::ReturnNil => (OpPrefix::RETURN_NIL, vec![]),
+            Instr::BuildList { count } => (OpPrefix::BUILD_LIST, vec![count]),
+            Instr::IndexGet => (OpPrefix::INDEX_GET, vec![]),
+            Instr::IndexSet => (OpPrefix::INDEX_SET, vec![]),
+            Instr::Invoke { method, argc } => (OpPrefix::INVOKE, vec![method.into(), argc]),
+            Instr::String { idx } => (OpPrefix::STRING, vec![idx]),
+            Instr::BuildMap { count } => (OpPrefix::BUILD_MAP, vec![count]),
+            Instr::Throw => (OpPrefix::THROW, vec![]),
+            Instr::BuildString { count } => (OpPrefix::BUILD_STRING, vec![count]),
+            Instr::Modulo => (OpPrefix::MODULO, vec![]),
+            Instr::Power => (OpPrefix::POWER, vec![]),
+            Instr::IndexPeek => (OpPrefix::INDEX_PEEK, vec![]),
+            Instr::IndexPostAdd => (OpPrefix::INDEX_POST_ADD, vec![]),
+            Instr::Pop => (OpPrefix::POP, vec![]),
+            Instr::Jump { offset } => (OpPrefix::JUMP, offset.to_be_bytes().to_vec()),
+            Instr::JumpIfFalse { offset } => (OpPrefix::JUMP_IF_FALSE, offset.to_be_bytes().to_vec()),
+            Instr::JumpIfNil { offset } => (OpPrefix::JUMP_IF_NIL, offset.to_be_bytes().to_vec()),
+            Instr::JumpIfNotNil { offset } => (OpPrefix::JUMP_IF_NOT_NIL, offset.to_be_bytes().to_vec()),
+        }
+    }
 }
 
+impl Instr {
+    /// The offset of a jump instruction, or `None` for the other instructions.
+    pub fn jump_offset(&self) -> Option<u16> {
+        match *self {
+            Instr::Jump { offset } | Instr::JumpIfFalse { offset }
+            | Instr::JumpIfNil { offset } | Instr::JumpIfNotNil { offset } => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// The same jump with another offset. Other instructions are returned as is.
+    pub fn with_jump_offset(self, offset: u16) -> Self {
+        match self {
+            Instr::Jump { .. } => Instr::Jump { offset },
+            Instr::JumpIfFalse { .. } => Instr::JumpIfFalse { offset },
+            Instr::JumpIfNil { .. } => Instr::JumpIfNil { offset },
+            Instr::JumpIfNotNil { .. } => Instr::JumpIfNotNil { offset },
+            instr => instr,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum InstrError {
     BadOp { bytes: Vec<u8>, },
     // BadContext
@@ -75,12 +206,98 @@ pub fn next_instr_point<'a, I: Iterator<Item = &'a u8>>(iter: &mut I) -> Option<
         OpPrefix::NIL => { (Ok(Instr::Nil), 1) }, // [NIL]
         OpPrefix::TRUE => { (Ok(Instr::True), 1) }, // [TRUE]
         OpPrefix::FALSE => { (Ok(Instr::False), 1) }, // [FALSE]
+        OpPrefix::EQUAL => { (Ok(Instr::Equal), 1) }, // [EQUAL]
+        OpPrefix::GREATER => { (Ok(Instr::Greater), 1) }, // [GREATER]
+        OpPrefix::LESS => { (Ok(Instr::Less), 1) }, // [LESS]
+        OpPrefix::NOT_EQUAL => { (Ok(Instr::NotEqual), 1) }, // [NOT_EQUAL]
+        OpPrefix::GREATER_EQUAL => { (Ok(Instr::GreaterEqual), 1) }, // [GREATER_EQUAL]
+        OpPrefix::LESS_EQUAL => { (Ok(Instr::LessEqual), 1) }, // [LESS_EQUAL]
         OpPrefix::ADD => { (Ok(Instr::Add), 1) }, // [ADD]
         OpPrefix::SUBTRACT => { (Ok(Instr::Subtract), 1) }, // [SUBTRACT]
         OpPrefix::MULTIPLY => { (Ok(Instr::Multiply), 1) }, // [MULTIPLY]
         OpPrefix::DIVIDE => { (Ok(Instr::Divide), 1) }, // [DIVIDE]
+        OpPrefix::NOT => { (Ok(Instr::Not), 1) }, // [NOT]
         OpPrefix::NEGATE => { (Ok(Instr::Negate), 1) }, // [NEGATE]
         OpPrefix::RETURN => { (Ok(Instr::Return), 1) }, // [RETURN]
+        OpPrefix::ADD_CONST => {
+            // [ADD_CONST] [CONST_IDX]
+            if let Some(&idx) = iter.next() {
+                (Ok(Instr::AddConst { idx }), 2)
+            } else {
+                (Err(BadOp{ bytes: vec![prefix.into()] }), 1)
+            }
+        },
+        OpPrefix::CONSTANT_0 => { (Ok(Instr::Constant0), 1) }, // [CONSTANT_0]
+        OpPrefix::CONSTANT_1 => { (Ok(Instr::Constant1), 1) }, // [CONSTANT_1]
+        OpPrefix::RETURN_NIL => { (Ok(Instr::ReturnNil), 1) }, // [RETURN_NIL]
+        OpPrefix::BUILD_LIST => {
+            // [BUILD_LIST] [COUNT]
+            if let Some(&count) = iter.next() {
+                (Ok(Instr::BuildList { count }), 2)
+            } else {
+                (Err(BadOp{ bytes: vec![prefix.into()] }), 1)
+            }
+        },
+        OpPrefix::STRING => {
+            // [STRING] [IDX]
+            if let Some(&idx) = iter.next() {
+                (Ok(Instr::String { idx }), 2)
+            } else {
+                (Err(BadOp{ bytes: vec![prefix.into()] }), 1)
+            }
+        },
+        OpPrefix::BUILD_MAP => {
+            // [BUILD_MAP] [COUNT]
+            if let Some(&count) = iter.next() {
+                (Ok(Instr::BuildMap { count }), 2)
+            } else {
+                (Err(BadOp{ bytes: vec![prefix.into()] }), 1)
+            }
+        },
+        OpPrefix::THROW => { (Ok(Instr::Throw), 1) }, // [THROW]
+        OpPrefix::MODULO => { (Ok(Instr::Modulo), 1) }, // [MODULO]
+        OpPrefix::POWER => { (Ok(Instr::Power), 1) }, // [POWER]
+        OpPrefix::INDEX_PEEK => { (Ok(Instr::IndexPeek), 1) }, // [INDEX_PEEK]
+        OpPrefix::INDEX_POST_ADD => { (Ok(Instr::IndexPostAdd), 1) }, // [INDEX_POST_ADD]
+        OpPrefix::BUILD_STRING => {
+            // [BUILD_STRING] [COUNT]
+            if let Some(&count) = iter.next() {
+                (Ok(Instr::BuildString { count }), 2)
+            } else {
+                (Err(BadOp{ bytes: vec![prefix.into()] }), 1)
+            }
+        },
+        OpPrefix::POP => { (Ok(Instr::Pop), 1) }, // [POP]
+        OpPrefix::JUMP | OpPrefix::JUMP_IF_FALSE | OpPrefix::JUMP_IF_NIL | OpPrefix::JUMP_IF_NOT_NIL => {
+            // [JUMP] [OFFSET_HI] [OFFSET_LO]
+            match (iter.next(), iter.next()) {
+                (Some(&hi), Some(&lo)) => {
+                    let offset = u16::from_be_bytes([hi, lo]);
+                    let instr = match prefix {
+                        OpPrefix::JUMP => Instr::Jump { offset },
+                        OpPrefix::JUMP_IF_FALSE => Instr::JumpIfFalse { offset },
+                        OpPrefix::JUMP_IF_NIL => Instr::JumpIfNil { offset },
+                        _ => Instr::JumpIfNotNil { offset },
+                    };
+                    (Ok(instr), 3)
+                },
+                (Some(&hi), None) => (Err(BadOp{ bytes: vec![prefix.into(), hi] }), 2),
+                _ => (Err(BadOp{ bytes: vec![prefix.into()] }), 1),
+            }
+        },
+        OpPrefix::INDEX_GET => { (Ok(Instr::IndexGet), 1) }, // [INDEX_GET]
+        OpPrefix::INDEX_SET => { (Ok(Instr::IndexSet), 1) }, // [INDEX_SET]
+        OpPrefix::INVOKE => {
+            // [INVOKE] [METHOD] [ARGC]
+            match (iter.next(), iter.next()) {
+                (Some(&method), Some(&argc)) => match Method::try_from(method) {
+                    Ok(method) => (Ok(Instr::Invoke { method, argc }), 3),
+                    Err(_) => (Err(BadOp{ bytes: vec![prefix.into(), method, argc] }), 3),
+                },
+                (Some(&method), None) => (Err(BadOp{ bytes: vec![prefix.into(), method] }), 2),
+                _ => (Err(BadOp{ bytes: vec![prefix.into()] }), 1),
+            }
+        },
         
         OpPrefix::UNKNOWN(byte) => {
             (Err(BadOp{ bytes: vec![byte] }), 1)
@@ -92,12 +309,17 @@ pub fn next_instr_point<'a, I: Iterator<Item = &'a u8>>(iter: &mut I) -> Option<
 
 pub struct ContextedInstrResult<'a> {
     ires: &'a InstrResult,
-    consts: &'a Vec<Value>,
+    chunk: &'a Chunk,
 }
 
 impl<'a> ContextedInstrResult<'a> {
-    pub fn new(ires: &'a InstrResult, consts: &'a Vec<Value>) -> Self {
-        Self { ires, consts }
+    pub fn new(ires: &'a InstrResult, chunk: &'a Chunk) -> Self {
+        Self { ires, chunk }
+    }
+
+    /// The constant at `idx`, for display. A malformed chunk may lack it.
+    fn constant(&self, idx: u8) -> String {
+        self.chunk.get_const(idx).map_or_else(|| "<missing>".to_string(), |val| val.to_string())
     }
 }
 
@@ -106,7 +328,23 @@ impl<'a> fmt::Display for ContextedInstrResult<'a> {
         match self.ires {
             Ok(instr) => match instr {
                 Instr::Constant { idx } => {
-                    write!(f, "Constant [{}] = {}", idx, self.consts.get(usize::from(*idx)).unwrap())
+                    write!(f, "Constant [{}] = {}", idx, self.constant(*idx))
+                },
+                Instr::AddConst { idx } => {
+                    write!(f, "AddConst [{}] = {}", idx, self.constant(*idx))
+                },
+                Instr::Constant0 => {
+                    write!(f, "Constant0 = {}", self.constant(0))
+                },
+                Instr::Constant1 => {
+                    write!(f, "Constant1 = {}", self.constant(1))
+                },
+                Instr::Invoke { method, argc } => {
+                    write!(f, "Invoke {}({})", method.name(), argc)
+                },
+                Instr::String { idx } => match self.chunk.get_string(*idx) {
+                    Some(chars) => write!(f, "String [{}] = {:?}", idx, chars),
+                    None => write!(f, "String [{}] = <missing>", idx),
                 },
                 // Instr::Nil => { write!(f, "Nil") },
                 // Instr::True => { write!(f, "True") },
@@ -118,6 +356,7 @@ impl<'a> fmt::Display for ContextedInstrResult<'a> {
                 // Instr::Subtract => { write!(f, "Subtract") },
                 // Instr::Multiply => { write!(f, "Multiply") },
                 // Instr::Divide => { write!(f, "Divide") },
+                // Instr::Not => { write!(f, "Not") },
                 // Instr::Negate => { write!(f, "Negate") },
                 // Instr::Return => { write!(f, "Return") },
                 _ => { write!(f, "{:?}", instr) },
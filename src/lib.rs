@@ -1,9 +1,13 @@
 pub mod chunk;
 pub mod value;
+pub mod object;
+pub mod native;
 pub mod instr;
 pub mod token;
 
 pub mod scanner;
 pub mod compiler;
+pub mod optimizer;
+pub mod register;
 
 pub mod vm;
\ No newline at end of file
@@ -1,9 +1,9 @@
 use rlox::{
     // instr::OpPrefix,
-    // chunk::Chunk,
+    chunk::Chunk,
     // value::Value,
-    // vm::VM,
-    scanner::Scanner,
+    vm::{VM, InterpretError},
+    register::{RegChunk, vm::RegisterVM},
 };
 use std::{
     env,
@@ -14,11 +14,11 @@ use std::{
 fn main() {
     // let mut chunk = Chunk::new();
 
-    // chunk.write_const(Value::Number(1.2), 123);
-    // chunk.write_const(Value::Number(3.4), 123);
+    // chunk.write_const(Value::from(1.2), 123);
+    // chunk.write_const(Value::from(3.4), 123);
     // chunk.write(OpPrefix::ADD, 123);
 
-    // chunk.write_const(Value::Number(5.6), 123);
+    // chunk.write_const(Value::from(5.6), 123);
     // chunk.write(OpPrefix::DIVIDE, 123);
     // chunk.write(OpPrefix::NEGATE, 123);
 
@@ -33,27 +33,49 @@ fn main() {
     // let mut vm = VM::new(chunk);
     // vm.run();
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 { // no repl here
-        eprintln!("Usage: rlox <path>");
-        process::exit(64);
+    let mut optimize = false;
+    let mut register = false;
+    let mut strict = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "-O" => optimize = true,
+            "--register" => register = true,
+            "--strict" => strict = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
     }
-
-    let path = &args[1];
+    let Some(path) = path else { usage() }; // no repl here
 
     // read bytes
-    let source = match fs::read_to_string(path) {
+    let source = match fs::read_to_string(&path) {
         Ok(source) => source,
         Err(err) => {
-            eprintln!("Failed to read file \"{}\". ({})", path, err.to_string());
+            eprintln!("Failed to read file \"{}\". ({})", path, err);
             process::exit(74)
         }
     };
 
-    let scanner = Scanner::from_source(&source);
-
-    for token in scanner {
-        println!("{:?}",token);
+    let result = if register {
+        let mut vm = RegisterVM::new(RegChunk::new());
+        vm.set_optimize(optimize);
+        vm.set_strict(strict);
+        vm.interpret(&source)
+    } else {
+        let mut vm = VM::new(Chunk::new());
+        vm.set_optimize(optimize);
+        vm.set_strict(strict);
+        vm.interpret(&source)
+    };
+    match result {
+        Ok(()) => {},
+        Err(InterpretError::CompileError) => process::exit(65),
+        Err(_) => process::exit(70),
     }
+}
 
+fn usage() -> ! {
+    eprintln!("Usage: rlox [-O] [--register] [--strict] <path>");
+    process::exit(64);
 }
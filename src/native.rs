@@ -0,0 +1,300 @@
+//! Operations on the built-in objects: indexing, and native methods called as `receiver.name(args)`.
+//!
+//! Both VMs share these. A failing operation returns the message of its runtime error,
+//! or that it ran out of heap (see `Heap::set_limit`).
+
+use crate::object::{Heap, HeapLimitExceeded, Key, Map, Obj};
+use crate::value::Value;
+use num_enum::{ IntoPrimitive, TryFromPrimitive };
+use std::mem;
+
+/// Native methods. Names are resolved at compile time, and the receiver is checked at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum Method {
+    Push,
+    Pop,
+    Insert,
+    Remove,
+    Len,
+    Slice,
+    Contains,
+    Keys,
+    Values,
+    Has,
+}
+
+const NAMES: [(&str, Method); 10] = [
+    ("push", Method::Push),
+    ("pop", Method::Pop),
+    ("insert", Method::Insert),
+    ("remove", Method::Remove),
+    ("len", Method::Len),
+    ("slice", Method::Slice),
+    ("contains", Method::Contains),
+    ("keys", Method::Keys),
+    ("values", Method::Values),
+    ("has", Method::Has),
+];
+
+impl Method {
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMES.iter().find(|(n, _)| *n == name).map(|(_, method)| *method)
+    }
+
+    pub fn name(self) -> &'static str {
+        NAMES.iter().find(|(_, method)| *method == self).map(|(n, _)| *n).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NativeError {
+    /// A runtime error, with its message.
+    Message(String),
+    /// An allocation would exceed the heap's limit.
+    HeapLimit,
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        NativeError::Message(message)
+    }
+}
+
+impl From<HeapLimitExceeded> for NativeError {
+    fn from(_: HeapLimitExceeded) -> Self {
+        NativeError::HeapLimit
+    }
+}
+
+pub type NativeResult = Result<Value, NativeError>;
+
+fn check_arity<T>(args: &[T], min: usize, max: usize) -> Result<(), String> {
+    match args.len() {
+        n if (min..=max).contains(&n) => Ok(()),
+        n if min == max => Err(format!("Expected {} arguments but got {}.", min, n)),
+        n => Err(format!("Expected {} or {} arguments but got {}.", min, max, n)),
+    }
+}
+
+/// Resolve a list index, which counts from the end if negative.
+/// `len` itself is only accepted if `inclusive`, e.g. to insert at the end.
+fn list_index(index: Value, len: usize, inclusive: bool) -> Result<usize, String> {
+    let Ok(num) = f64::try_from(index) else {
+        return Err("List index must be a number.".to_string());
+    };
+    if num.fract() != 0.0 {
+        return Err("List index must be an integer.".to_string());
+    }
+
+    let idx = if num < 0.0 { num + len as f64 } else { num };
+    let end = if inclusive { len as f64 } else { len as f64 - 1.0 };
+    if (0.0..=end).contains(&idx) {
+        Ok(idx as usize)
+    } else {
+        Err("List index out of range.".to_string())
+    }
+}
+
+/// Resolve a slice bound: like an index, but clamped to the list.
+fn slice_bound(bound: Value, len: usize) -> Result<usize, String> {
+    let Ok(num) = f64::try_from(bound) else {
+        return Err("List index must be a number.".to_string());
+    };
+    if num.fract() != 0.0 {
+        return Err("List index must be an integer.".to_string());
+    }
+
+    let idx = if num < 0.0 { num + len as f64 } else { num };
+    Ok(idx.clamp(0.0, len as f64) as usize)
+}
+
+/// The map key for `value`. Only numbers, strings, booleans and `nil` are hashable.
+pub fn map_key(heap: &Heap, value: Value) -> Result<Key, String> {
+    if let Ok(num) = f64::try_from(value) {
+        Ok(Key::number(num))
+    } else if value.is_nil() {
+        Ok(Key::Nil)
+    } else if let Some(obj) = value.as_obj() {
+        match heap.get(obj) {
+            Obj::String(_) => Ok(Key::String(obj)),
+            _ => Err("Map key must be a number, string, boolean or nil.".to_string()),
+        }
+    } else {
+        Ok(Key::Bool(bool::from(value)))
+    }
+}
+
+/// `{k1: v1, k2: v2}` from `[k1, v1, k2, v2]`. A repeated key keeps its first position and its last value.
+pub fn build_map(heap: &mut Heap, entries: &[Value]) -> NativeResult {
+    let mut map = Map::default();
+    for entry in entries.chunks(2) {
+        let value = entry.get(1).copied().unwrap_or(Value::from(()));
+        map.insert(map_key(heap, entry[0])?, value);
+    }
+    Ok(Value::from(heap.alloc(Obj::Map(map))?))
+}
+
+/// `a == b` in strict mode. Like `Value::checked_eq`, but objects of different kinds,
+/// e.g. a string and a list, are values of different types too.
+pub fn strict_eq(heap: &Heap, a: Value, b: Value) -> NativeResult {
+    if let (Some(x), Some(y)) = (a.as_obj(), b.as_obj()) {
+        if mem::discriminant(heap.get(x)) != mem::discriminant(heap.get(y)) {
+            return Err(NativeError::Message("Operands must be of the same type.".to_string()));
+        }
+    }
+    Ok(a.checked_eq(b).map_err(|_| "Operands must be of the same type.".to_string())?)
+}
+
+/// `a + b`, which adds two numbers or concatenates two strings.
+/// The caller must collect garbage beforehand if needed, as the result may be allocated.
+pub fn add(heap: &mut Heap, a: Value, b: Value) -> NativeResult {
+    if let (Some(x), Some(y)) = (a.as_obj(), b.as_obj()) {
+        if let (Obj::String(x), Obj::String(y)) = (heap.get(x), heap.get(y)) {
+            let chars = format!("{}{}", x, y);
+            return Ok(Value::from(heap.intern(&chars)?));
+        }
+    }
+    Ok(a.checked_add(b).map_err(|_| "Operands must be two numbers or two strings.".to_string())?)
+}
+
+/// The string interpolating `parts`, e.g. `["a", 1, nil]` into `a1nil`.
+pub fn build_string(heap: &mut Heap, parts: &[Value]) -> NativeResult {
+    let chars: String = parts.iter().map(|&part| heap.display(part).to_string()).collect();
+    Ok(Value::from(heap.intern(&chars)?))
+}
+
+/// `target[index]`. A key missing from a map evaluates to `nil`.
+pub fn index_get(heap: &Heap, target: Value, index: Value) -> NativeResult {
+    match target.as_obj().map(|obj| heap.get(obj)) {
+        Some(Obj::List(items)) => Ok(items[list_index(index, items.len(), false)?]),
+        Some(Obj::Map(map)) => Ok(map.get(map_key(heap, index)?).unwrap_or(Value::from(()))),
+        _ => Err(NativeError::Message("Only lists and maps can be indexed.".to_string())),
+    }
+}
+
+/// `target[index] = value`, which evaluates to `value`.
+pub fn index_set(heap: &mut Heap, target: Value, index: Value, value: Value) -> NativeResult {
+    let Some(obj) = target.as_obj() else {
+        return Err(NativeError::Message("Only lists and maps can be indexed.".to_string()));
+    };
+    let key = map_key(heap, index);
+    Ok(heap.modify(obj, |obj| match obj {
+        Obj::List(items) => {
+            let idx = list_index(index, items.len(), false)?;
+            items[idx] = value;
+            Ok(value)
+        },
+        Obj::Map(map) => {
+            map.insert(key?, value);
+            Ok(value)
+        },
+        _ => Err("Only lists and maps can be indexed.".to_string()),
+    })??)
+}
+
+/// `target[index] += delta` for postfix `++` and `--`, which evaluates to the old value.
+pub fn index_post_add(heap: &mut Heap, target: Value, index: Value, delta: Value) -> NativeResult {
+    let old = index_get(heap, target, index)?;
+    let new = old.checked_add(delta).map_err(|_| "Operands must be numbers.".to_string())?;
+    index_set(heap, target, index, new)?;
+    Ok(old)
+}
+
+/// What a method evaluates to: new lists can't be allocated while the receiver is being modified.
+enum Outcome {
+    Value(Value),
+    NewList(Vec<Value>),
+}
+
+/// `receiver.method(args)`. The caller must collect garbage beforehand if needed,
+/// as methods may allocate while `receiver` and `args` are not rooted anymore.
+pub fn invoke(heap: &mut Heap, method: Method, receiver: Value, args: &[Value]) -> NativeResult {
+    let Some(obj) = receiver.as_obj() else {
+        return Err(NativeError::Message("Only lists and maps have methods.".to_string()));
+    };
+    // keys are resolved up front, as the heap is borrowed while the receiver is modified.
+    let keys: Vec<_> = args.iter().map(|&arg| map_key(heap, arg)).collect();
+
+    let outcome = heap.modify(obj, |obj| match obj {
+        Obj::List(items) => list_method(items, method, args),
+        Obj::Map(map) => map_method(map, method, &keys),
+        Obj::String(_) => Err("Only lists and maps have methods.".to_string()),
+    })??;
+
+    match outcome {
+        Outcome::Value(value) => Ok(value),
+        Outcome::NewList(items) => Ok(Value::from(heap.alloc(Obj::List(items))?)),
+    }
+}
+
+fn list_method(items: &mut Vec<Value>, method: Method, args: &[Value]) -> Result<Outcome, String> {
+    let nil = Value::from(());
+    match method {
+        Method::Push => {
+            check_arity(args, 1, 1)?;
+            items.push(args[0]);
+            Ok(Outcome::Value(nil))
+        },
+        Method::Pop => {
+            check_arity(args, 0, 0)?;
+            items.pop().map(Outcome::Value).ok_or_else(|| "Can't pop from an empty list.".to_string())
+        },
+        Method::Insert => {
+            check_arity(args, 2, 2)?;
+            let idx = list_index(args[0], items.len(), true)?;
+            items.insert(idx, args[1]);
+            Ok(Outcome::Value(nil))
+        },
+        Method::Remove => {
+            check_arity(args, 1, 1)?;
+            let idx = list_index(args[0], items.len(), false)?;
+            Ok(Outcome::Value(items.remove(idx)))
+        },
+        Method::Len => {
+            check_arity(args, 0, 0)?;
+            Ok(Outcome::Value(Value::from(items.len() as f64)))
+        },
+        Method::Slice => {
+            check_arity(args, 1, 2)?;
+            let start = slice_bound(args[0], items.len())?;
+            let end = match args.get(1) {
+                Some(&end) => slice_bound(end, items.len())?,
+                None => items.len(),
+            };
+            Ok(Outcome::NewList(items[start..end.max(start)].to_vec()))
+        },
+        Method::Contains => {
+            check_arity(args, 1, 1)?;
+            Ok(Outcome::Value(Value::from(items.contains(&args[0]))))
+        },
+        Method::Keys | Method::Values | Method::Has => Err(format!("Lists have no method '{}'.", method.name())),
+    }
+}
+
+fn map_method(map: &mut Map, method: Method, keys: &[Result<Key, String>]) -> Result<Outcome, String> {
+    let key = |i: usize| keys[i].clone();
+    match method {
+        Method::Len => {
+            check_arity(keys, 0, 0)?;
+            Ok(Outcome::Value(Value::from(map.len() as f64)))
+        },
+        Method::Has => {
+            check_arity(keys, 1, 1)?;
+            Ok(Outcome::Value(Value::from(map.get(key(0)?).is_some())))
+        },
+        Method::Remove => {
+            check_arity(keys, 1, 1)?;
+            Ok(Outcome::Value(map.remove(key(0)?).unwrap_or(Value::from(()))))
+        },
+        Method::Keys => {
+            check_arity(keys, 0, 0)?;
+            Ok(Outcome::NewList(map.keys().collect()))
+        },
+        Method::Values => {
+            check_arity(keys, 0, 0)?;
+            Ok(Outcome::NewList(map.values().collect()))
+        },
+        _ => Err(format!("Maps have no method '{}'.", method.name())),
+    }
+}
@@ -0,0 +1,359 @@
+//! Heap objects, and the mark-sweep garbage collector which owns them.
+//!
+//! clox links objects through raw pointers. Here they live in the slots of a `Heap`,
+//! and values hold a copyable `ObjRef` handle to their slot, so `Value` stays `Copy`.
+//!
+//! Strings are interned like in clox, so equal strings are the same object,
+//! and `Value`'s `PartialEq` compares them by content for free.
+
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+/// A handle to an object in a `Heap`. Objects are compared by identity.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ObjRef(u32);
+
+impl ObjRef {
+    pub fn index(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_index(index: u32) -> Self {
+        ObjRef(index)
+    }
+}
+
+pub enum Obj {
+    String(Box<str>),
+    List(Vec<Value>),
+    Map(Map),
+}
+
+impl Obj {
+    /// Bytes owned by the object, for the collector's bookkeeping and `Limits::max_heap_bytes`.
+    fn size(&self) -> usize {
+        mem::size_of::<Obj>() + match self {
+            Obj::String(chars) => chars.len(),
+            Obj::List(items) => items.capacity() * mem::size_of::<Value>(),
+            Obj::Map(map) => map.entries.capacity() * mem::size_of::<(Key, Value)>()
+                + map.index.capacity() * mem::size_of::<(Key, usize)>(),
+        }
+    }
+
+    /// Push the objects directly referenced by the object to `gray`.
+    fn trace(&self, gray: &mut Vec<ObjRef>) {
+        match self {
+            Obj::String(_) => {},
+            Obj::List(items) => gray.extend(items.iter().filter_map(|item| item.as_obj())),
+            Obj::Map(map) => {
+                for (key, value) in &map.entries {
+                    gray.extend(Value::from(*key).as_obj());
+                    gray.extend(value.as_obj());
+                }
+            },
+        }
+    }
+}
+
+/// A hashable value, used as a map key. Numbers are compared by their bits,
+/// with `-0` and every NaN normalized, so that a key always finds itself again.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(ObjRef),
+}
+
+impl Key {
+    pub fn number(num: f64) -> Self {
+        let num = if num == 0.0 { 0.0 } else if num.is_nan() { f64::NAN } else { num };
+        Key::Number(num.to_bits())
+    }
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Self {
+        match key {
+            Key::Nil => Value::from(()),
+            Key::Bool(b) => Value::from(b),
+            Key::Number(bits) => Value::from(f64::from_bits(bits)),
+            Key::String(obj) => Value::from(obj),
+        }
+    }
+}
+
+/// A hash map which iterates in insertion order.
+#[derive(Default)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    index: HashMap<Key, usize>, // position of each key in `entries`
+}
+
+impl Map {
+    pub fn get(&self, key: Key) -> Option<Value> {
+        self.index.get(&key).map(|&i| self.entries[i].1)
+    }
+
+    /// Insert or overwrite the entry of `key`. A new key is ordered last.
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key, self.entries.len());
+                self.entries.push((key, value));
+            },
+        }
+    }
+
+    /// Remove the entry of `key`, and returns its value. This is linear, to keep the order.
+    pub fn remove(&mut self, key: Key) -> Option<Value> {
+        let i = self.index.remove(&key)?;
+        let (_, value) = self.entries.remove(i);
+        for pos in self.index.values_mut() {
+            if *pos > i {
+                *pos -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = Value> + '_ {
+        self.entries.iter().map(|(key, _)| Value::from(*key))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = Value> + '_ {
+        self.entries.iter().map(|(_, value)| *value)
+    }
+}
+
+/// Collect when the heap grows past this many bytes, at first.
+const FIRST_GC: usize = 1024 * 1024;
+/// After a collection, the next one happens when the heap has grown by this factor.
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+struct Slot {
+    obj: Obj,
+    marked: bool,
+}
+
+/// An allocation would grow the heap past its limit, see `Heap::set_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapLimitExceeded;
+
+pub struct Heap {
+    slots: Vec<Option<Slot>>,
+    free: Vec<u32>, // indices of empty slots, reused before growing `slots`
+    strings: HashMap<Box<str>, ObjRef>, // the interned strings. weak: collected strings are removed
+    bytes: usize,
+    next_gc: usize,
+    max_bytes: Option<usize>, // see `Heap::set_limit`
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap { slots: vec![], free: vec![], strings: HashMap::new(), bytes: 0, next_gc: FIRST_GC, max_bytes: None }
+    }
+
+    /// Limit the bytes owned by objects. An allocation which would grow the heap past `max_bytes` fails.
+    pub fn set_limit(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+    }
+
+    fn check_limit(&self, bytes: usize) -> Result<(), HeapLimitExceeded> {
+        match self.max_bytes {
+            Some(max) if bytes > max => Err(HeapLimitExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> Result<ObjRef, HeapLimitExceeded> {
+        self.check_limit(self.bytes + obj.size())?;
+        self.bytes += obj.size();
+        let slot = Some(Slot { obj, marked: false });
+
+        if let Some(index) = self.free.pop() {
+            self.slots[index as usize] = slot;
+            Ok(ObjRef(index))
+        } else {
+            self.slots.push(slot);
+            Ok(ObjRef(u32::try_from(self.slots.len() - 1).expect("heap should have less than 2^32 objects")))
+        }
+    }
+
+    /// The string object with the contents `chars`, allocated on its first use.
+    pub fn intern(&mut self, chars: &str) -> Result<ObjRef, HeapLimitExceeded> {
+        if let Some(&obj) = self.strings.get(chars) {
+            return Ok(obj);
+        }
+        let obj = self.alloc(Obj::String(chars.into()))?;
+        self.strings.insert(chars.into(), obj);
+        Ok(obj)
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        &self.slots[obj.0 as usize].as_ref().expect("object should be alive").obj
+    }
+
+    /// Mutate an object, keeping track of how its size changes.
+    /// The size is only known afterwards, so growing past the limit is reported after the change.
+    pub fn modify<R>(&mut self, obj: ObjRef, f: impl FnOnce(&mut Obj) -> R) -> Result<R, HeapLimitExceeded> {
+        let obj = &mut self.slots[obj.0 as usize].as_mut().expect("object should be alive").obj;
+        let before = obj.size();
+        let result = f(obj);
+        self.bytes = self.bytes - before + obj.size();
+        self.check_limit(self.bytes)?;
+        Ok(result)
+    }
+
+    /// Bytes owned by live (and not yet collected) objects.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The number of live (and not yet collected) objects.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the heap has grown enough since the last collection to collect again.
+    /// With a limit, also once half of it is used, so that garbage doesn't count against it.
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "debug-stress-gc")
+            || self.bytes > self.next_gc
+            || self.max_bytes.is_some_and(|max| self.bytes > max / 2)
+    }
+
+    /// Free every object which is not reachable from `roots`.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Value>) {
+        // mark
+        let mut gray: Vec<ObjRef> = roots.into_iter().filter_map(Value::as_obj).collect();
+        while let Some(obj) = gray.pop() {
+            let Some(slot) = self.slots[obj.0 as usize].as_mut() else { continue };
+            if slot.marked {
+                continue;
+            }
+            slot.marked = true;
+            slot.obj.trace(&mut gray);
+        }
+
+        // like clox's `tableRemoveWhite`: the string table doesn't keep strings alive.
+        let slots = &self.slots;
+        self.strings.retain(|_, obj| slots[obj.0 as usize].as_ref().is_some_and(|slot| slot.marked));
+
+        // sweep
+        for (index, entry) in self.slots.iter_mut().enumerate() {
+            match entry {
+                Some(slot) if slot.marked => slot.marked = false,
+                Some(slot) => {
+                    self.bytes -= slot.obj.size();
+                    *entry = None;
+                    self.free.push(index as u32);
+                },
+                None => {},
+            }
+        }
+
+        self.next_gc = (self.bytes * GC_HEAP_GROW_FACTOR).max(FIRST_GC);
+    }
+
+    /// Display `value`, including the contents of the objects it references.
+    pub fn display(&self, value: Value) -> Printed<'_> {
+        Printed { heap: self, value }
+    }
+}
+
+pub struct Printed<'a> {
+    heap: &'a Heap,
+    value: Value,
+}
+
+impl<'a> Printed<'a> {
+    /// `visiting` are the containers being printed, so that a list containing itself prints as `[...]`.
+    /// Strings are printed as they are, but quoted inside containers, so that `["a, b"]` is not `["a", "b"]`.
+    fn fmt_value(&self, f: &mut fmt::Formatter, value: Value, visiting: &mut Vec<ObjRef>) -> fmt::Result {
+        let Some(obj) = value.as_obj() else {
+            return write!(f, "{}", value);
+        };
+        if visiting.contains(&obj) {
+            return match self.heap.get(obj) {
+                Obj::Map(_) => write!(f, "{{...}}"),
+                _ => write!(f, "[...]"),
+            };
+        }
+
+        visiting.push(obj);
+        match self.heap.get(obj) {
+            Obj::String(chars) if visiting.len() == 1 => write!(f, "{}", chars)?,
+            Obj::String(chars) => write_quoted(f, chars)?,
+            Obj::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_value(f, *item, visiting)?;
+                }
+                write!(f, "]")?;
+            },
+            Obj::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    self.fmt_value(f, Value::from(*key), visiting)?;
+                    write!(f, ": ")?;
+                    self.fmt_value(f, *value, visiting)?;
+                }
+                write!(f, "}}")?;
+            },
+        }
+        visiting.pop();
+        Ok(())
+    }
+}
+
+/// Write `chars` as a string literal which scans back to them.
+fn write_quoted(f: &mut fmt::Formatter, chars: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    let mut chars = chars.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '$' if chars.peek() == Some(&'{') => write!(f, "\\$")?, // not an interpolation
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", u32::from(c))?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl<'a> fmt::Display for Printed<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_value(f, self.value, &mut vec![])
+    }
+}
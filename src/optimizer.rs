@@ -0,0 +1,165 @@
+use crate::chunk::Chunk;
+use crate::instr::Instr;
+use crate::value::Value;
+
+use std::mem;
+
+/// Peephole optimization over the bytecode of `chunk`, in place.
+///
+/// Every instruction keeps the line of the instruction it originates from,
+/// so that runtime errors are still reported on the same line.
+/// A chunk with undecodable bytes is left untouched.
+pub fn optimize(chunk: &mut Chunk) {
+    let mut instrs = Vec::new();
+    let mut offsets = Vec::new(); // of each instruction, and of the end of the code
+    for (ires, offset) in chunk.iter() {
+        match ires {
+            Ok(instr) => instrs.push((instr, chunk.line_of(offset))),
+            Err(_) => return,
+        }
+        offsets.push(offset);
+    }
+    offsets.push(chunk.code.len());
+
+    // jumps are tracked by the index of their target instruction, as offsets change with rewriting.
+    // a chunk with a jump into the middle of an instruction is left untouched as well.
+    let mut targets = Vec::with_capacity(instrs.len());
+    for (i, (instr, _)) in instrs.iter().enumerate() {
+        let target = match instr.jump_offset() {
+            Some(jump) => match offsets.binary_search(&(offsets[i + 1] + usize::from(jump))) {
+                Ok(target) => Some(target),
+                Err(_) => return,
+            },
+            None => None,
+        };
+        targets.push(target);
+    }
+
+    // a jump to a jump which is sure to be taken, as it is unconditional or tests the same condition
+    // on the same value, goes straight to that jump's target instead. jumps only go forward,
+    // so the later jumps are threaded first, and the offset only grows as far as the original code allows.
+    for i in (0..instrs.len()).rev() {
+        let Some(target) = targets[i] else { continue };
+        let Some(&(next, _)) = instrs.get(target) else { continue };
+        let taken = matches!(next, Instr::Jump { .. }) || mem::discriminant(&next) == mem::discriminant(&instrs[i].0);
+        match targets[target] {
+            Some(next_target) if taken && offsets[next_target] - offsets[i + 1] <= u16::MAX.into() => {
+                targets[i] = Some(next_target);
+            },
+            _ => {},
+        }
+    }
+
+    let mut is_target = vec![false; instrs.len() + 1];
+    for &target in targets.iter().flatten() {
+        is_target[target] = true;
+    }
+    let last_target = targets.iter().flatten().max().copied();
+
+    // the optimized code is built as a stack: each incoming instruction is pushed,
+    // and then rewritten together with the instructions before it, as long as any rule applies.
+    // an instruction which is jumped to begins a new basic block, so it is never rewritten
+    // together with the instructions before it: `fence` is where the current block begins in `out`.
+    let mut out: Vec<(Instr, usize)> = Vec::with_capacity(instrs.len());
+    let mut moved = vec![0; instrs.len() + 1]; // index in `out` of each jump target
+    let mut fence = 0;
+    for (i, &(instr, line)) in instrs.iter().enumerate() {
+        if is_target[i] {
+            fence = out.len();
+            moved[i] = fence;
+        }
+        out.push((instr, line));
+        while rewrite(&mut out, fence, chunk) {}
+
+        // nothing after a return can ever be reached, unless it is jumped to.
+        if matches!(instr, Instr::Return | Instr::ReturnNil) && !matches!(last_target, Some(target) if target > i) {
+            break;
+        }
+    }
+    moved[instrs.len()] = out.len();
+
+    // jumps are never rewritten, so their indices in `out` are found by counting them.
+    let jumps: Vec<usize> = targets.iter().flatten().map(|&target| moved[target]).collect();
+    let mut out_offsets = Vec::with_capacity(out.len() + 1);
+    let mut len = 0;
+    for (instr, _) in out.iter_mut() {
+        *instr = specialize(*instr);
+        out_offsets.push(len);
+        len += 1 + instr.encode().1.len();
+    }
+    out_offsets.push(len);
+
+    chunk.clear_code();
+    let mut jumps = jumps.into_iter();
+    for (k, (instr, line)) in out.into_iter().enumerate() {
+        let instr = match instr.jump_offset() {
+            Some(_) => {
+                let target = jumps.next().expect("every jump should be kept");
+                let offset = out_offsets[target] - out_offsets[k + 1];
+                instr.with_jump_offset(u16::try_from(offset).expect("optimized jumps should not grow"))
+            },
+            None => instr,
+        };
+        chunk.write_instr(instr, line);
+    }
+}
+
+/// Rewrite the tail of `out` from `fence` on, if any rule applies. Returns whether it did.
+///
+/// The replacement is reported on the line of the replaced instruction which may fail at runtime,
+/// or of the first replaced instruction if none may.
+fn rewrite(out: &mut Vec<(Instr, usize)>, fence: usize, chunk: &mut Chunk) -> bool {
+    use Instr::*;
+
+    let (replacement, len, line) = match &out[fence..] {
+        // a value which is only pushed to be popped
+        [.., (Constant { .. } | String { .. } | Nil | True | False, _), (Pop, _)] => {
+            out.truncate(out.len() - 2);
+            return true;
+        },
+
+        // constant negation. folding a non-number would lose its runtime error.
+        [.., (Constant { idx }, line), (Negate, _)] => {
+            let Some(Ok(num)) = chunk.get_const(*idx).map(f64::try_from) else { return false };
+            if chunk.const_count() > u8::MAX.into() {
+                return false; // the pool is full
+            }
+            (Constant { idx: chunk.add_const(Value::from(-num)) }, 2, *line)
+        },
+
+        // `!` of a literal
+        [.., (Constant { idx }, line), (Not, _)] => match chunk.get_const(*idx) {
+            Some(val) => (if bool::from(val) { False } else { True }, 2, *line),
+            None => return false, // malformed: leave it to the VM's error
+        },
+        [.., (True, line), (Not, _)] => (False, 2, *line),
+        [.., (Nil | False, line), (Not, _)] => (True, 2, *line),
+
+        // compare-and-not. orderings are left alone, as `!(a < b)` is not `a >= b` for NaN.
+        [.., (Equal, line), (Not, _)] => (NotEqual, 2, *line),
+        [.., (NotEqual, line), (Not, _)] => (Equal, 2, *line),
+
+        // `!!!x` is `!x`
+        [.., (Not, line), (Not, _), (Not, _)] => (Not, 3, *line),
+
+        // superinstructions
+        [.., (Constant { idx }, _), (Add, line)] => (AddConst { idx: *idx }, 2, *line),
+        [.., (Nil, line), (Return, _)] => (ReturnNil, 2, *line),
+
+        _ => return false,
+    };
+
+    out.truncate(out.len() - len);
+    out.push((replacement, line));
+    true
+}
+
+/// Loads of the first two constants don't need an operand.
+/// This is done last, as the other rules match on `Constant`.
+fn specialize(instr: Instr) -> Instr {
+    match instr {
+        Instr::Constant { idx: 0 } => Instr::Constant0,
+        Instr::Constant { idx: 1 } => Instr::Constant1,
+        instr => instr,
+    }
+}
@@ -0,0 +1,118 @@
+//! An experimental register-based backend, as an alternative to the stack machine.
+//!
+//! Code is compiled to the usual stack `Chunk` first, and then translated
+//! (`compiler::translate`) into three-address instructions (`Add dst, a, b`),
+//! which are run by their own loop (`vm::RegisterVM`).
+//! Selected with `--register` on the command line.
+//!
+//! There are only `instr::MAX_REGISTERS` registers, one per stack slot. Code which needs more,
+//! e.g. a list literal of 128 elements or deeply nested operands, is rejected by `translate`.
+
+pub mod instr;
+pub mod compiler;
+pub mod vm;
+
+use crate::chunk::Chunk;
+use crate::value::Value;
+use instr::{Instr, InstrResult, ContextedInstrResult, next_instr_point};
+
+/// Register code. The bytes, lines and constants are stored like a stack `Chunk`'s.
+#[derive(Clone)]
+pub struct RegChunk {
+    base: Chunk,
+    registers: usize,
+}
+
+impl Default for RegChunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegChunk {
+    pub fn new() -> RegChunk {
+        RegChunk { base: Chunk::new(), registers: 0 }
+    }
+
+    /// An empty chunk sharing the constants and strings of `chunk`.
+    fn with_consts_of(chunk: &Chunk) -> RegChunk {
+        let mut base = chunk.clone();
+        base.clear_code();
+        RegChunk { base, registers: 0 }
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.base.code
+    }
+
+    /// The number of registers the code uses.
+    pub fn registers(&self) -> usize {
+        self.registers
+    }
+
+    pub fn iter(&self) -> CodeIterator<'_> {
+        CodeIterator { code: self.code(), offset: 0 }
+    }
+
+    pub fn write<B>(&mut self, byte: B, line: usize)
+    where B: Into<u8>
+    {
+        self.base.write(byte, line);
+    }
+
+    pub fn write_instr(&mut self, instr: Instr, line: usize) {
+        let (op, operands) = instr.encode();
+        self.write(op, line);
+        for byte in operands {
+            self.write(byte, line);
+        }
+    }
+
+    pub fn get_const(&self, idx: u8) -> Option<Value> {
+        self.base.get_const(idx)
+    }
+
+    pub fn get_string(&self, idx: u8) -> Option<&str> {
+        self.base.get_string(idx)
+    }
+
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.base.line_of(offset)
+    }
+
+    pub fn disasm_all(&self, name: &str) {
+        println!("=== {} ({} registers) ===", name, self.registers);
+
+        let mut prev_line_no = usize::MAX;
+
+        for (ires, offset) in self.iter() {
+            let line_no = self.line_of(offset);
+
+            let line = if prev_line_no == line_no {
+                "   |".to_string()
+            } else {
+                prev_line_no = line_no;
+                format!("{:4}", line_no)
+            };
+
+            println!("{:04} {} {}", offset, line, ContextedInstrResult::new(&ires, self));
+        }
+    }
+}
+
+pub struct CodeIterator<'a> {
+    code: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for CodeIterator<'a> {
+    type Item = (InstrResult, usize);
+    fn next(&mut self) -> Option<Self::Item> {
+        let prev_offset = self.offset;
+
+        let (res, len) = next_instr_point(&self.code[self.offset..])?;
+        self.offset += len;
+
+        Some((res, prev_offset))
+    }
+}
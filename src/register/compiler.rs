@@ -0,0 +1,278 @@
+use super::RegChunk;
+use super::instr::{Instr, Operand, MAX_REGISTERS};
+use crate::chunk::Chunk;
+use crate::instr::Instr as StackInstr;
+use std::collections::BTreeMap;
+
+/// Translate stack code into register code, or report why it can't be.
+///
+/// Stack slot `i` becomes register `i`. Constants are not loaded, but used as operands
+/// directly where the stack code would have pushed them, so e.g. `1 + 2` is a single `Add`.
+/// Each register instruction keeps the line of the stack instruction it comes from.
+///
+/// Every slot is loaded into its register at jumps and at their targets,
+/// so that all the paths to a target agree on where the values are.
+pub fn translate(chunk: &Chunk) -> Option<RegChunk> {
+    let mut translator = Translator {
+        out: RegChunk::with_consts_of(chunk),
+        stack: Vec::new(),
+        line: 0,
+        labels: BTreeMap::new(),
+        reachable: true,
+    };
+
+    match translator.translate(chunk) {
+        Ok(()) => Some(translator.out),
+        Err(message) => {
+            eprintln!("[line {}] Error: {}", translator.line, message);
+            None
+        },
+    }
+}
+
+struct Translator {
+    out: RegChunk,
+    stack: Vec<Operand>, // what each stack slot holds
+    line: usize,
+    labels: BTreeMap<usize, Label>, // by the offset of their target in the stack code
+    reachable: bool, // false after a `Return` or a `Jump`, until the next jump target
+}
+
+/// The forward jumps to the same target which are not patched yet.
+struct Label {
+    depth: usize, // the number of stack slots at the target
+    jumps: Vec<usize>, // the offsets of the register jumps' `offset` operands
+}
+
+impl Translator {
+    fn translate(&mut self, chunk: &Chunk) -> Result<(), &'static str> {
+        use StackInstr::*;
+
+        for (ires, offset) in chunk.iter() {
+            self.line = chunk.line_of(offset);
+            let Ok(instr) = ires else { return Err("Bad instruction.") };
+
+            self.land(offset)?;
+            if !self.reachable {
+                continue;
+            }
+
+            match instr {
+                Constant { idx } => self.push_const(idx)?,
+                Constant0 => self.push_const(0)?,
+                Constant1 => self.push_const(1)?,
+                Nil => { let dst = self.push()?; self.emit(Instr::LoadNil { dst }); },
+                True => { let dst = self.push()?; self.emit(Instr::LoadTrue { dst }); },
+                False => { let dst = self.push()?; self.emit(Instr::LoadFalse { dst }); },
+
+                Equal => self.binary(|dst, a, b| Instr::Equal { dst, a, b })?,
+                Greater => self.binary(|dst, a, b| Instr::Greater { dst, a, b })?,
+                Less => self.binary(|dst, a, b| Instr::Less { dst, a, b })?,
+                NotEqual => self.binary(|dst, a, b| Instr::NotEqual { dst, a, b })?,
+                GreaterEqual => self.binary(|dst, a, b| Instr::GreaterEqual { dst, a, b })?,
+                LessEqual => self.binary(|dst, a, b| Instr::LessEqual { dst, a, b })?,
+                Add => self.binary(|dst, a, b| Instr::Add { dst, a, b })?,
+                Subtract => self.binary(|dst, a, b| Instr::Subtract { dst, a, b })?,
+                Multiply => self.binary(|dst, a, b| Instr::Multiply { dst, a, b })?,
+                Divide => self.binary(|dst, a, b| Instr::Divide { dst, a, b })?,
+                Modulo => self.binary(|dst, a, b| Instr::Modulo { dst, a, b })?,
+                Power => self.binary(|dst, a, b| Instr::Power { dst, a, b })?,
+                AddConst { idx } => {
+                    self.push_const(idx)?;
+                    self.binary(|dst, a, b| Instr::Add { dst, a, b })?;
+                },
+
+                Not => self.unary(|dst, a| Instr::Not { dst, a })?,
+                Negate => self.unary(|dst, a| Instr::Negate { dst, a })?,
+
+                BuildList { count } => {
+                    self.materialize(count.into())?;
+                    self.stack.truncate(self.stack.len() - usize::from(count));
+                    let dst = self.push()?;
+                    self.emit(Instr::BuildList { dst, count });
+                },
+                String { idx } => { let dst = self.push()?; self.emit(Instr::LoadString { dst, idx }); },
+                BuildString { count } => {
+                    self.materialize(count.into())?;
+                    self.stack.truncate(self.stack.len() - usize::from(count));
+                    let dst = self.push()?;
+                    self.emit(Instr::BuildString { dst, count });
+                },
+                BuildMap { count } => {
+                    let len = 2 * usize::from(count);
+                    self.materialize(len)?;
+                    self.stack.truncate(self.stack.len() - len);
+                    let dst = self.push()?;
+                    self.emit(Instr::BuildMap { dst, count });
+                },
+                IndexGet => self.binary(|dst, a, b| Instr::IndexGet { dst, a, b })?,
+                IndexSet => self.ternary(|dst, a, b, c| Instr::IndexSet { dst, a, b, c })?,
+                IndexPeek => {
+                    // the target and index stay in their slots, for the following `IndexSet`.
+                    let (a, b) = match self.stack[..] {
+                        [.., a, b] => (a, b),
+                        _ => return Err("Stack underflow."),
+                    };
+                    let dst = self.push()?;
+                    self.emit(Instr::IndexGet { dst, a, b });
+                },
+                IndexPostAdd => self.ternary(|dst, a, b, c| Instr::IndexPostAdd { dst, a, b, c })?,
+                Invoke { method, argc } => {
+                    self.materialize(usize::from(argc) + 1)?;
+                    self.stack.truncate(self.stack.len() - usize::from(argc) - 1);
+                    let dst = self.push()?;
+                    self.emit(Instr::Invoke { dst, method, argc });
+                },
+
+                Pop => { self.pop()?; },
+                Jump { offset: jump } => {
+                    self.materialize(self.stack.len())?;
+                    self.emit(Instr::Jump { offset: 0 });
+                    self.add_jump(offset + 3 + usize::from(jump))?;
+                    self.reachable = false;
+                },
+                JumpIfFalse { offset: jump } => self.jump_if(offset + 3 + usize::from(jump), |a, offset| Instr::JumpIfFalse { a, offset })?,
+                JumpIfNil { offset: jump } => self.jump_if(offset + 3 + usize::from(jump), |a, offset| Instr::JumpIfNil { a, offset })?,
+                JumpIfNotNil { offset: jump } => self.jump_if(offset + 3 + usize::from(jump), |a, offset| Instr::JumpIfNotNil { a, offset })?,
+
+                Throw => {
+                    let a = self.pop()?;
+                    self.emit(Instr::Throw { a });
+                    // the stack code still expects a value in its place, though it's never produced.
+                    self.push()?;
+                },
+
+                Return => {
+                    let a = self.pop()?;
+                    self.emit(Instr::Return { a });
+                    self.reachable = false;
+                },
+                ReturnNil => {
+                    let dst = self.push()?;
+                    self.emit(Instr::LoadNil { dst });
+                    self.emit(Instr::Return { a: Operand::Reg(dst) });
+                    self.reachable = false;
+                },
+            }
+        }
+
+        // jumps to the end of the code end it, like a return.
+        self.land(chunk.code.len())?;
+        if !self.labels.is_empty() {
+            return Err("Bad jump target."); // past the end of the code
+        }
+        Ok(())
+    }
+
+    /// Emit a conditional jump on the top slot, which is not popped.
+    fn jump_if(&mut self, target: usize, instr: fn(Operand, u16) -> Instr) -> Result<(), &'static str> {
+        self.materialize(self.stack.len())?;
+        let a = *self.stack.last().ok_or("Stack underflow.")?;
+        self.emit(instr(a, 0));
+        self.add_jump(target)
+    }
+
+    /// Register the jump just emitted, to be patched when its target in the stack code is reached.
+    fn add_jump(&mut self, target: usize) -> Result<(), &'static str> {
+        let depth = self.stack.len();
+        let label = self.labels.entry(target).or_insert(Label { depth, jumps: vec![] });
+        if label.depth != depth {
+            return Err("Inconsistent stack at a jump target.");
+        }
+        label.jumps.push(self.out.code().len() - 2);
+        Ok(())
+    }
+
+    /// Patch the jumps to the stack code at `offset`, if any, to the register code emitted next.
+    /// Code which is only reached by jumps gets the stack they left.
+    fn land(&mut self, offset: usize) -> Result<(), &'static str> {
+        if self.labels.first_key_value().is_some_and(|(&target, _)| target < offset) {
+            return Err("Bad jump target."); // into the middle of an instruction
+        }
+        let Some(label) = self.labels.remove(&offset) else { return Ok(()) };
+
+        if self.reachable {
+            self.materialize(self.stack.len())?;
+            if self.stack.len() != label.depth {
+                return Err("Inconsistent stack at a jump target.");
+            }
+        } else {
+            self.stack = (0..label.depth).map(|reg| Operand::Reg(reg as u8)).collect();
+            self.reachable = true;
+        }
+
+        let here = self.out.code().len();
+        for operand in label.jumps {
+            let jump = u16::try_from(here - operand - 2).map_err(|_| "Too much code to jump over.")?;
+            self.out.base.code[operand..operand + 2].copy_from_slice(&jump.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        self.out.write_instr(instr, self.line);
+    }
+
+    /// Push a slot held in its own register, and returns the register.
+    fn push(&mut self) -> Result<u8, &'static str> {
+        let reg = u8::try_from(self.stack.len()).ok().filter(|&reg| reg < MAX_REGISTERS).ok_or("Too many registers.")?;
+        self.stack.push(Operand::Reg(reg));
+        self.out.registers = self.out.registers.max(self.stack.len());
+        Ok(reg)
+    }
+
+    /// Push a constant, which is only loaded into a register if it doesn't fit in an operand.
+    fn push_const(&mut self, idx: u8) -> Result<(), &'static str> {
+        // operand bytes fit as many constants as registers.
+        if idx < MAX_REGISTERS {
+            self.stack.push(Operand::Const(idx));
+        } else {
+            let dst = self.push()?;
+            self.emit(Instr::LoadConst { dst, idx });
+        }
+        Ok(())
+    }
+
+    /// Load the constants among the top `count` slots into their registers,
+    /// for instructions which take a run of consecutive registers.
+    fn materialize(&mut self, count: usize) -> Result<(), &'static str> {
+        let start = self.stack.len().checked_sub(count).ok_or("Stack underflow.")?;
+        for slot in start..self.stack.len() {
+            if let Operand::Const(idx) = self.stack[slot] {
+                let dst = u8::try_from(slot).ok().filter(|&reg| reg < MAX_REGISTERS).ok_or("Too many registers.")?;
+                self.emit(Instr::LoadConst { dst, idx });
+                self.stack[slot] = Operand::Reg(dst);
+            }
+        }
+        self.out.registers = self.out.registers.max(self.stack.len());
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Operand, &'static str> {
+        self.stack.pop().ok_or("Stack underflow.")
+    }
+
+    fn binary(&mut self, instr: fn(u8, Operand, Operand) -> Instr) -> Result<(), &'static str> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let dst = self.push()?;
+        self.emit(instr(dst, a, b));
+        Ok(())
+    }
+
+    fn ternary(&mut self, instr: fn(u8, Operand, Operand, Operand) -> Instr) -> Result<(), &'static str> {
+        let c = self.pop()?;
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let dst = self.push()?;
+        self.emit(instr(dst, a, b, c));
+        Ok(())
+    }
+
+    fn unary(&mut self, instr: fn(u8, Operand) -> Instr) -> Result<(), &'static str> {
+        let a = self.pop()?;
+        let dst = self.push()?;
+        self.emit(instr(dst, a));
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+use super::RegChunk;
+use crate::instr::InstrError;
+use crate::native::Method;
+use std::fmt;
+use num_enum::{ FromPrimitive, IntoPrimitive };
+
+/// Operand bytes below this are registers, and the rest are constants (`byte - MAX_REGISTERS`),
+/// so an operand can be used without loading it first, as in Lua's RK operands.
+pub const MAX_REGISTERS: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(u8),
+    Const(u8),
+}
+
+impl Operand {
+    pub fn encode(self) -> u8 {
+        match self {
+            Operand::Reg(reg) => reg,
+            Operand::Const(idx) => MAX_REGISTERS + idx,
+        }
+    }
+
+    pub fn decode(byte: u8) -> Self {
+        match byte.checked_sub(MAX_REGISTERS) {
+            Some(idx) => Operand::Const(idx),
+            None => Operand::Reg(byte),
+        }
+    }
+}
+
+/// OpCode of the register machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+#[allow(non_camel_case_types)] // named like `instr::OpPrefix`
+pub enum OpCode {
+    LOAD_CONST = 0,
+    LOAD_NIL,
+    LOAD_TRUE,
+    LOAD_FALSE,
+    EQUAL,
+    GREATER,
+    LESS,
+    NOT_EQUAL,
+    GREATER_EQUAL,
+    LESS_EQUAL,
+    ADD,
+    SUBTRACT,
+    MULTIPLY,
+    DIVIDE,
+    NOT,
+    NEGATE,
+    RETURN,
+    BUILD_LIST,
+    INDEX_GET,
+    INDEX_SET,
+    INVOKE,
+    LOAD_STRING,
+    BUILD_MAP,
+    THROW,
+    BUILD_STRING,
+    MODULO,
+    POWER,
+    INDEX_POST_ADD,
+    JUMP,
+    JUMP_IF_FALSE,
+    JUMP_IF_NIL,
+    JUMP_IF_NOT_NIL,
+    #[num_enum(catch_all)]
+    UNKNOWN(u8),
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A decoded register instruction. `dst` is always a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    LoadConst { dst: u8, idx: u8 }, // for constants which don't fit in an operand
+    LoadNil { dst: u8 },
+    LoadTrue { dst: u8 },
+    LoadFalse { dst: u8 },
+    Equal { dst: u8, a: Operand, b: Operand },
+    Greater { dst: u8, a: Operand, b: Operand },
+    Less { dst: u8, a: Operand, b: Operand },
+    NotEqual { dst: u8, a: Operand, b: Operand },
+    GreaterEqual { dst: u8, a: Operand, b: Operand },
+    LessEqual { dst: u8, a: Operand, b: Operand },
+    Add { dst: u8, a: Operand, b: Operand },
+    Subtract { dst: u8, a: Operand, b: Operand },
+    Multiply { dst: u8, a: Operand, b: Operand },
+    Divide { dst: u8, a: Operand, b: Operand },
+    Not { dst: u8, a: Operand },
+    Negate { dst: u8, a: Operand },
+    Return { a: Operand },
+    BuildList { dst: u8, count: u8 }, // of the registers from `dst` on
+    IndexGet { dst: u8, a: Operand, b: Operand },
+    IndexSet { dst: u8, a: Operand, b: Operand, c: Operand },
+    Invoke { dst: u8, method: Method, argc: u8 }, // the receiver is in `dst`, followed by the arguments
+    LoadString { dst: u8, idx: u8 },
+    BuildMap { dst: u8, count: u8 }, // of the key-value pairs in the registers from `dst` on
+    Throw { a: Operand },
+    BuildString { dst: u8, count: u8 }, // of the parts in the registers from `dst` on
+    Modulo { dst: u8, a: Operand, b: Operand },
+    Power { dst: u8, a: Operand, b: Operand },
+    IndexPostAdd { dst: u8, a: Operand, b: Operand, c: Operand }, // `a[b] += c`, evaluating to the old value
+    // forward jumps, by `offset` bytes from the end of the instruction, like the stack machine's.
+    Jump { offset: u16 },
+    JumpIfFalse { a: Operand, offset: u16 },
+    JumpIfNil { a: Operand, offset: u16 },
+    JumpIfNotNil { a: Operand, offset: u16 },
+}
+
+impl Instr {
+    /// Returns the opcode, and the operand bytes of the instruction.
+    /// This is the inverse of `next_instr_point`.
+    pub fn encode(&self) -> (OpCode, Vec<u8>) {
+        use Instr::*;
+        match *self {
+            LoadConst { dst, idx } => (OpCode::LOAD_CONST, vec![dst, idx]),
+            LoadNil { dst } => (OpCode::LOAD_NIL, vec![dst]),
+            LoadTrue { dst } => (OpCode::LOAD_TRUE, vec![dst]),
+            LoadFalse { dst } => (OpCode::LOAD_FALSE, vec![dst]),
+            Equal { dst, a, b } => (OpCode::EQUAL, vec![dst, a.encode(), b.encode()]),
+            Greater { dst, a, b } => (OpCode::GREATER, vec![dst, a.encode(), b.encode()]),
+            Less { dst, a, b } => (OpCode::LESS, vec![dst, a.encode(), b.encode()]),
+            NotEqual { dst, a, b } => (OpCode::NOT_EQUAL, vec![dst, a.encode(), b.encode()]),
+            GreaterEqual { dst, a, b } => (OpCode::GREATER_EQUAL, vec![dst, a.encode(), b.encode()]),
+            LessEqual { dst, a, b } => (OpCode::LESS_EQUAL, vec![dst, a.encode(), b.encode()]),
+            Add { dst, a, b } => (OpCode::ADD, vec![dst, a.encode(), b.encode()]),
+            Subtract { dst, a, b } => (OpCode::SUBTRACT, vec![dst, a.encode(), b.encode()]),
+            Multiply { dst, a, b } => (OpCode::MULTIPLY, vec![dst, a.encode(), b.encode()]),
+            Divide { dst, a, b } => (OpCode::DIVIDE, vec![dst, a.encode(), b.encode()]),
+            Not { dst, a } => (OpCode::NOT, vec![dst, a.encode()]),
+            Negate { dst, a } => (OpCode::NEGATE, vec![dst, a.encode()]),
+            Return { a } => (OpCode::RETURN, vec![a.encode()]),
+            BuildList { dst, count } => (OpCode::BUILD_LIST, vec![dst, count]),
+            IndexGet { dst, a, b } => (OpCode::INDEX_GET, vec![dst, a.encode(), b.encode()]),
+            IndexSet { dst, a, b, c } => (OpCode::INDEX_SET, vec![dst, a.encode(), b.encode(), c.encode()]),
+            Invoke { dst, method, argc } => (OpCode::INVOKE, vec![dst, method.into(), argc]),
+            LoadString { dst, idx } => (OpCode::LOAD_STRING, vec![dst, idx]),
+            BuildMap { dst, count } => (OpCode::BUILD_MAP, vec![dst, count]),
+            Throw { a } => (OpCode::THROW, vec![a.encode()]),
+            BuildString { dst, count } => (OpCode::BUILD_STRING, vec![dst, count]),
+            Modulo { dst, a, b } => (OpCode::MODULO, vec![dst, a.encode(), b.encode()]),
+            Power { dst, a, b } => (OpCode::POWER, vec![dst, a.encode(), b.encode()]),
+            IndexPostAdd { dst, a, b, c } => (OpCode::INDEX_POST_ADD, vec![dst, a.encode(), b.encode(), c.encode()]),
+            Jump { offset } => (OpCode::JUMP, offset.to_be_bytes().to_vec()),
+            JumpIfFalse { a, offset } => (OpCode::JUMP_IF_FALSE, [&[a.encode()][..], &offset.to_be_bytes()].concat()),
+            JumpIfNil { a, offset } => (OpCode::JUMP_IF_NIL, [&[a.encode()][..], &offset.to_be_bytes()].concat()),
+            JumpIfNotNil { a, offset } => (OpCode::JUMP_IF_NOT_NIL, [&[a.encode()][..], &offset.to_be_bytes()].concat()),
+        }
+    }
+}
+
+pub type InstrResult = Result<Instr, InstrError>;
+
+/// Decode the instruction at the start of `code`, and returns it with its length in bytes.
+pub fn next_instr_point(code: &[u8]) -> Option<(InstrResult, usize)> {
+    use Instr::*;
+
+    let (&byte, operands) = code.split_first()?;
+    let op = OpCode::from(byte);
+
+    let len = match op {
+        OpCode::LOAD_NIL | OpCode::LOAD_TRUE | OpCode::LOAD_FALSE | OpCode::RETURN | OpCode::THROW => 1,
+        OpCode::LOAD_CONST | OpCode::NOT | OpCode::NEGATE
+        | OpCode::BUILD_LIST | OpCode::LOAD_STRING | OpCode::BUILD_MAP | OpCode::BUILD_STRING | OpCode::JUMP => 2,
+        OpCode::INDEX_SET | OpCode::INDEX_POST_ADD => 4,
+        OpCode::UNKNOWN(_) => return Some((Err(InstrError::BadOp { bytes: vec![byte] }), 1)),
+        _ => 3,
+    };
+    let Some(operands) = operands.get(..len) else {
+        // a truncated instruction takes the rest of the code
+        return Some((Err(InstrError::BadOp { bytes: code.to_vec() }), code.len()));
+    };
+
+    let reg = |i: usize| operands[i];
+    let opnd = |i: usize| Operand::decode(operands[i]);
+    let offset = |i: usize| u16::from_be_bytes([operands[i], operands[i + 1]]);
+    let instr = match op {
+        OpCode::LOAD_CONST => LoadConst { dst: reg(0), idx: reg(1) },
+        OpCode::LOAD_NIL => LoadNil { dst: reg(0) },
+        OpCode::LOAD_TRUE => LoadTrue { dst: reg(0) },
+        OpCode::LOAD_FALSE => LoadFalse { dst: reg(0) },
+        OpCode::EQUAL => Equal { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::GREATER => Greater { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::LESS => Less { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::NOT_EQUAL => NotEqual { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::GREATER_EQUAL => GreaterEqual { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::LESS_EQUAL => LessEqual { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::ADD => Add { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::SUBTRACT => Subtract { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::MULTIPLY => Multiply { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::DIVIDE => Divide { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::NOT => Not { dst: reg(0), a: opnd(1) },
+        OpCode::NEGATE => Negate { dst: reg(0), a: opnd(1) },
+        OpCode::RETURN => Return { a: opnd(0) },
+        OpCode::THROW => Throw { a: opnd(0) },
+        OpCode::BUILD_STRING => BuildString { dst: reg(0), count: reg(1) },
+        OpCode::MODULO => Modulo { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::POWER => Power { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::INDEX_POST_ADD => IndexPostAdd { dst: reg(0), a: opnd(1), b: opnd(2), c: opnd(3) },
+        OpCode::JUMP => Jump { offset: offset(0) },
+        OpCode::JUMP_IF_FALSE => JumpIfFalse { a: opnd(0), offset: offset(1) },
+        OpCode::JUMP_IF_NIL => JumpIfNil { a: opnd(0), offset: offset(1) },
+        OpCode::JUMP_IF_NOT_NIL => JumpIfNotNil { a: opnd(0), offset: offset(1) },
+        OpCode::BUILD_LIST => BuildList { dst: reg(0), count: reg(1) },
+        OpCode::LOAD_STRING => LoadString { dst: reg(0), idx: reg(1) },
+        OpCode::BUILD_MAP => BuildMap { dst: reg(0), count: reg(1) },
+        OpCode::INDEX_GET => IndexGet { dst: reg(0), a: opnd(1), b: opnd(2) },
+        OpCode::INDEX_SET => IndexSet { dst: reg(0), a: opnd(1), b: opnd(2), c: opnd(3) },
+        OpCode::INVOKE => match Method::try_from(operands[1]) {
+            Ok(method) => Invoke { dst: reg(0), method, argc: reg(2) },
+            Err(_) => return Some((Err(InstrError::BadOp { bytes: code[..1 + len].to_vec() }), 1 + len)),
+        },
+        OpCode::UNKNOWN(_) => unreachable!(),
+    };
+
+    Some((Ok(instr), 1 + len))
+}
+
+pub struct ContextedInstrResult<'a> {
+    ires: &'a InstrResult,
+    chunk: &'a RegChunk,
+}
+
+impl<'a> ContextedInstrResult<'a> {
+    pub fn new(ires: &'a InstrResult, chunk: &'a RegChunk) -> Self {
+        Self { ires, chunk }
+    }
+
+    /// The constant at `idx`, for display. A malformed chunk may lack it.
+    fn constant(&self, idx: u8) -> String {
+        self.chunk.get_const(idx).map_or_else(|| "<missing>".to_string(), |val| val.to_string())
+    }
+
+    fn operand(&self, f: &mut fmt::Formatter, operand: Operand) -> fmt::Result {
+        match operand {
+            Operand::Reg(reg) => write!(f, "r{}", reg),
+            Operand::Const(idx) => write!(f, "[{}] = {}", idx, self.constant(idx)),
+        }
+    }
+}
+
+impl<'a> fmt::Display for ContextedInstrResult<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instr::*;
+        let instr = match self.ires {
+            Ok(instr) => instr,
+            Err(InstrError::BadOp { bytes }) => return write!(f, "<BadOp {:02X?}>", bytes),
+        };
+
+        let (op, _) = instr.encode();
+        let (dst, operands) = match *instr {
+            LoadConst { dst, idx } => return write!(f, "{} r{}, [{}] = {}", op, dst, idx, self.constant(idx)),
+            LoadNil { dst } | LoadTrue { dst } | LoadFalse { dst } => return write!(f, "{} r{}", op, dst),
+            BuildList { dst, count } | BuildMap { dst, count } | BuildString { dst, count } => return write!(f, "{} r{}, {}", op, dst, count),
+            LoadString { dst, idx } => match self.chunk.get_string(idx) {
+                Some(chars) => return write!(f, "{} r{}, [{}] = {:?}", op, dst, idx, chars),
+                None => return write!(f, "{} r{}, [{}] = <missing>", op, dst, idx),
+            },
+            Invoke { dst, method, argc } => return write!(f, "{} r{}, {}({})", op, dst, method.name(), argc),
+            Jump { offset } => return write!(f, "{} +{}", op, offset),
+            JumpIfFalse { a, offset } | JumpIfNil { a, offset } | JumpIfNotNil { a, offset } => {
+                write!(f, "{} ", op)?;
+                self.operand(f, a)?;
+                return write!(f, ", +{}", offset);
+            },
+            Equal { dst, a, b } | Greater { dst, a, b } | Less { dst, a, b }
+            | NotEqual { dst, a, b } | GreaterEqual { dst, a, b } | LessEqual { dst, a, b }
+            | Add { dst, a, b } | Subtract { dst, a, b } | Multiply { dst, a, b } | Divide { dst, a, b }
+            | Modulo { dst, a, b } | Power { dst, a, b } | IndexGet { dst, a, b } => (Some(dst), vec![a, b]),
+            IndexSet { dst, a, b, c } | IndexPostAdd { dst, a, b, c } => (Some(dst), vec![a, b, c]),
+            Not { dst, a } | Negate { dst, a } => (Some(dst), vec![a]),
+            Return { a } | Throw { a } => (None, vec![a]),
+        };
+
+        write!(f, "{}", op)?;
+        let mut sep = " ";
+        if let Some(dst) = dst {
+            write!(f, " r{}", dst)?;
+            sep = ", ";
+        }
+        for operand in operands {
+            f.write_str(sep)?;
+            self.operand(f, operand)?;
+            sep = ", ";
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use super::RegChunk;
+use super::compiler::translate;
+use super::instr::{OpCode, Operand};
+use crate::value::Value;
+use crate::object::{Heap, Obj};
+use crate::native::{self, Method, NativeError};
+use crate::compiler::compile;
+use crate::optimizer::optimize;
+use crate::vm::{InterpretError, InterpretResult, Limits, Meter};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// write `Value::$method` of two operands to the destination register,
+/// or report `$message` as a runtime error.
+macro_rules! binary_op {
+    ($self:ident, $start:ident, $method:ident, $message:expr) => {{
+        let Some([dst, a, b]) = $self.read_bytes() else {
+            return $self.runtime_error("Bad instruction.", $start);
+        };
+        let Some([a, b]) = $self.operands([a, b]) else {
+            return $self.runtime_error("Bad instruction.", $start);
+        };
+        match a.$method(b) {
+            Ok(val) => $self.set(dst, val),
+            _ => return $self.runtime_error($message, $start),
+        }
+    }};
+}
+
+/// The register machine. It enforces the same execution `Limits` as `vm::VM`,
+/// except that `max_stack` bounds the registers the code uses, checked before it runs.
+pub struct RegisterVM {
+    chunk: RegChunk,
+    ip: usize,
+    registers: Box<[Value; 256]>,
+
+    limits: Limits,
+    interrupt: Arc<AtomicBool>,
+    heap: Heap,
+
+    strict: bool, // see `VM::set_strict`
+    optimize: bool, // see `VM::set_optimize`
+}
+
+impl RegisterVM {
+    pub fn new(chunk: RegChunk) -> Self {
+        Self::with_limits(chunk, Limits::default())
+    }
+
+    pub fn with_limits(chunk: RegChunk, limits: Limits) -> Self {
+        let mut heap = Heap::new();
+        heap.set_limit(limits.max_heap_bytes);
+        RegisterVM {
+            chunk,
+            ip: 0,
+            // every byte is a valid register, so accesses don't need bounds checks.
+            registers: Box::new([Value::from(()); 256]),
+            limits,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            heap,
+            strict: false,
+            optimize: false,
+        }
+    }
+
+    /// See `VM::interrupt_handle`.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupt)
+    }
+
+    /// Run the peephole optimizer over the stack code before translating it, in `RegisterVM::interpret`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// See `VM::set_strict`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// collect garbage before an allocation, if the heap has grown enough.
+    /// everything reachable is in the registers the code uses.
+    fn maybe_collect(&mut self) {
+        if self.heap.should_collect() {
+            self.heap.collect(self.registers[..self.chunk.registers()].iter().copied());
+        }
+    }
+
+    /// run the chunk from the beginning.
+    pub fn run(&mut self) -> InterpretResult {
+        let result = self.execute();
+        self.interrupt.store(false, Ordering::Relaxed);
+        result
+    }
+
+    fn execute(&mut self) -> InterpretResult {
+        self.ip = 0;
+        if matches!(self.limits.max_stack, Some(max) if self.chunk.registers() > max) {
+            return Err(InterpretError::StackOverflow);
+        }
+        let mut meter = Meter::start(&self.limits);
+
+        loop {
+            let start = self.ip;
+            let Some(byte) = self.read_byte() else {
+                return Ok(()); // code evaluated successfully
+            };
+            meter.tick(&self.limits, &self.interrupt)?;
+
+            match OpCode::from(byte) {
+                OpCode::LOAD_CONST => {
+                    let Some([dst, idx]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some(val) = self.chunk.get_const(idx) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.set(dst, val);
+                },
+                OpCode::LOAD_NIL | OpCode::LOAD_TRUE | OpCode::LOAD_FALSE => {
+                    let Some([dst]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let val = match OpCode::from(byte) {
+                        OpCode::LOAD_NIL => Value::from(()),
+                        op => Value::from(op == OpCode::LOAD_TRUE),
+                    };
+                    self.set(dst, val);
+                },
+                OpCode::EQUAL | OpCode::NOT_EQUAL => {
+                    let Some([dst, a, b]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a, b]) = self.operands([a, b]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let eq = if self.strict {
+                        match native::strict_eq(&self.heap, a, b) {
+                            Ok(eq) => bool::from(eq),
+                            Err(err) => return self.native_error(err, start),
+                        }
+                    } else {
+                        a == b // PartialEq for Value
+                    };
+                    self.set(dst, Value::from(eq == (OpCode::from(byte) == OpCode::EQUAL)));
+                },
+                OpCode::GREATER => binary_op!(self, start, checked_gt, "Operands must be numbers."),
+                OpCode::LESS => binary_op!(self, start, checked_lt, "Operands must be numbers."),
+                OpCode::GREATER_EQUAL => binary_op!(self, start, checked_ge, "Operands must be numbers."),
+                OpCode::LESS_EQUAL => binary_op!(self, start, checked_le, "Operands must be numbers."),
+                OpCode::ADD => {
+                    let Some([dst, a, b]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.maybe_collect();
+                    let Some([a, b]) = self.operands([a, b]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    match native::add(&mut self.heap, a, b) {
+                        Ok(val) => self.set(dst, val),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+                OpCode::SUBTRACT => binary_op!(self, start, checked_sub, "Operands must be numbers."),
+                OpCode::MULTIPLY => binary_op!(self, start, checked_mul, "Operands must be numbers."),
+                OpCode::DIVIDE => binary_op!(self, start, checked_div, "Operands must be numbers."),
+                OpCode::MODULO => binary_op!(self, start, checked_rem, "Operands must be numbers."),
+                OpCode::POWER => binary_op!(self, start, checked_pow, "Operands must be numbers."),
+                OpCode::NOT => {
+                    let Some([dst, a]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a]) = self.operands([a]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.set(dst, !a);
+                },
+                OpCode::NEGATE => {
+                    let Some([dst, a]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a]) = self.operands([a]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    match a.checked_neg() {
+                        Ok(val) => self.set(dst, val),
+                        _ => return self.runtime_error("Operand must be a number.", start),
+                    }
+                },
+
+                OpCode::BUILD_LIST => {
+                    let Some([dst, count]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.maybe_collect();
+                    let items = self.registers(dst, count.into()).to_vec();
+                    let list = self.heap.alloc(Obj::List(items)).map_err(|_| InterpretError::HeapLimitExceeded)?;
+                    self.set(dst, Value::from(list));
+                },
+                OpCode::LOAD_STRING => {
+                    let Some([dst, idx]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.maybe_collect();
+                    let Some(chars) = self.chunk.get_string(idx) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let string = self.heap.intern(chars).map_err(|_| InterpretError::HeapLimitExceeded)?;
+                    self.set(dst, Value::from(string));
+                },
+                OpCode::BUILD_STRING => {
+                    let Some([dst, count]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.maybe_collect();
+                    let parts = self.registers(dst, count.into()).to_vec();
+                    match native::build_string(&mut self.heap, &parts) {
+                        Ok(string) => self.set(dst, string),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+                OpCode::BUILD_MAP => {
+                    let Some([dst, count]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.maybe_collect();
+                    let entries = self.registers(dst, 2 * usize::from(count)).to_vec();
+                    match native::build_map(&mut self.heap, &entries) {
+                        Ok(map) => self.set(dst, map),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+                OpCode::INDEX_GET => {
+                    let Some([dst, a, b]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a, b]) = self.operands([a, b]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    match native::index_get(&self.heap, a, b) {
+                        Ok(val) => self.set(dst, val),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+                OpCode::INDEX_SET => {
+                    let Some([dst, a, b, c]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a, b, c]) = self.operands([a, b, c]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    match native::index_set(&mut self.heap, a, b, c) {
+                        Ok(val) => self.set(dst, val),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+                OpCode::INDEX_POST_ADD => {
+                    let Some([dst, a, b, c]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a, b, c]) = self.operands([a, b, c]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    match native::index_post_add(&mut self.heap, a, b, c) {
+                        Ok(val) => self.set(dst, val),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+                OpCode::INVOKE => {
+                    let Some([dst, method, argc]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Ok(method) = Method::try_from(method) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.maybe_collect();
+                    let receiver = self.registers[usize::from(dst)];
+                    let args = self.registers(dst.wrapping_add(1), argc.into()).to_vec();
+                    match native::invoke(&mut self.heap, method, receiver, &args) {
+                        Ok(val) => self.set(dst, val),
+                        Err(err) => return self.native_error(err, start),
+                    }
+                },
+
+                OpCode::JUMP => {
+                    let Some(offset) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    self.ip += usize::from(u16::from_be_bytes(offset));
+                },
+                OpCode::JUMP_IF_FALSE | OpCode::JUMP_IF_NIL | OpCode::JUMP_IF_NOT_NIL => {
+                    let Some([a, hi, lo]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a]) = self.operands([a]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let jump = match OpCode::from(byte) {
+                        OpCode::JUMP_IF_FALSE => !bool::from(a),
+                        OpCode::JUMP_IF_NIL => a.is_nil(),
+                        _ => !a.is_nil(),
+                    };
+                    if jump {
+                        self.ip += usize::from(u16::from_be_bytes([hi, lo]));
+                    }
+                },
+
+                OpCode::THROW => {
+                    let Some([a]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a]) = self.operands([a]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let message = format!("Uncaught exception: {}", self.heap.display(a));
+                    return self.runtime_error(&message, start);
+                },
+
+                OpCode::RETURN => {
+                    let Some([a]) = self.read_bytes() else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    let Some([a]) = self.operands([a]) else {
+                        return self.runtime_error("Bad instruction.", start);
+                    };
+                    println!("{}", self.heap.display(a));
+                    return Ok(());
+                },
+                OpCode::UNKNOWN(_) => {
+                    return self.runtime_error("Bad instruction.", start);
+                },
+            }
+        }
+    }
+
+    /// read the byte at `self.ip`, and advance `self.ip`.
+    #[inline]
+    fn read_byte(&mut self) -> Option<u8> {
+        let byte = *self.chunk.code().get(self.ip)?;
+        self.ip += 1;
+        Some(byte)
+    }
+
+    /// read the `N` operand bytes of an instruction at once, and advance `self.ip`.
+    #[inline]
+    fn read_bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.chunk.code().get(self.ip..self.ip + N)?.try_into().ok()?;
+        self.ip += N;
+        Some(bytes)
+    }
+
+    /// the values of the registers or constants named by operand bytes,
+    /// or `None` if a constant is missing from the chunk.
+    #[inline]
+    fn operands<const N: usize>(&self, bytes: [u8; N]) -> Option<[Value; N]> {
+        let mut vals = [Value::from(()); N];
+        for (val, byte) in vals.iter_mut().zip(bytes) {
+            *val = match Operand::decode(byte) {
+                Operand::Reg(reg) => self.registers[usize::from(reg)],
+                Operand::Const(idx) => self.chunk.get_const(idx)?,
+            };
+        }
+        Some(vals)
+    }
+
+    /// `count` consecutive registers from `first`, clamped to the register file.
+    fn registers(&self, first: u8, count: usize) -> &[Value] {
+        let first = usize::from(first);
+        &self.registers[first..(first + count).min(256)]
+    }
+
+    #[inline]
+    fn set(&mut self, dst: u8, val: Value) {
+        self.registers[usize::from(dst)] = val;
+    }
+
+    /// report a runtime error at the instruction starting at `offset`.
+    fn runtime_error(&mut self, message: &str, offset: usize) -> InterpretResult {
+        eprintln!("{}", message);
+
+        let line = self.chunk.line_of(offset);
+        eprintln!("[line {}] in script", line);
+
+        Err(InterpretError::RuntimeError)
+    }
+
+    /// report the failure of a native operation at the instruction starting at `offset`.
+    fn native_error(&mut self, err: NativeError, offset: usize) -> InterpretResult {
+        match err {
+            NativeError::Message(message) => self.runtime_error(&message, offset),
+            NativeError::HeapLimit => Err(InterpretError::HeapLimitExceeded),
+        }
+    }
+
+    /// compile source code `src` to stack code, translate it, and run it immediately.
+    pub fn interpret(&mut self, src: &str) -> InterpretResult {
+        let Some(mut chunk) = compile(src) else {
+            return Err(InterpretError::CompileError);
+        };
+        if self.optimize {
+            optimize(&mut chunk);
+        }
+
+        let Some(chunk) = translate(&chunk) else {
+            return Err(InterpretError::CompileError);
+        };
+
+        #[cfg(feature = "debug-print-code")]
+        chunk.disasm_all("register code");
+
+        self.chunk = chunk;
+        self.run()
+    }
+}
@@ -1,8 +1,6 @@
-use std::str::Chars;
-// use std::iter::Peekable;
-use peekmore::{PeekMore, PeekMoreIterator};
+use std::borrow::Cow;
 
-use crate::token::{ TokenType, Token, Handler, TokenResult };
+use crate::token::{ TokenType, Token, Literal, Handler, TokenResult };
 
 macro_rules! patt {
     // modified https://doc.rust-lang.org/src/core/macros/mod.rs.html#342
@@ -14,61 +12,67 @@ macro_rules! patt {
     };
 }
 
+/// A zero-copy scanner: lexemes are slices of `src`, addressed with byte offsets.
 pub struct Scanner<'a> {
-    chars: PeekMoreIterator<Chars<'a>>,
+    src: &'a str,
+    start: usize, // byte offset where the current lexeme begins
+    current: usize, // byte offset of the next character
     line: usize, // 0 if EOF token has been emitted.
-    lex: Vec<char>,
+    interpolations: Vec<usize>, // the number of unclosed `{` in each `${` being scanned, innermost last
 }
 
 impl<'a> Scanner<'a> {
     pub fn from_source(src: &'a str) -> Self{
         Scanner {
-            chars: src.chars().peekmore(),
+            src,
+            start: 0,
+            current: 0,
             line: 1,
-            lex: vec![]
+            interpolations: vec![],
         }
     }
 
-    /// advance `self.chars` and returns the character.
-    /// this is a wrapper for `self.chars.next()` with line incrementing and stack pushing.
+    /// The current lexeme.
+    #[inline]
+    fn lexeme(&self) -> &'a str {
+        &self.src[self.start..self.current]
+    }
+
+    /// Returns the following character without consuming it.
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.src[self.current..].chars().next()
+    }
+
+    /// Returns the unconsumed bytes, for lookahead over ASCII patterns.
+    #[inline]
+    fn rest(&self) -> &'a [u8] {
+        &self.src.as_bytes()[self.current..]
+    }
+
+    /// advance `self.current` and returns the character.
+    /// this is a wrapper for `self.peek()` with line incrementing.
     fn advance(&mut self) -> Option<char> {
-        let result = self.chars.next();
+        let result = self.peek();
         if let Some(c) = result {
             if c == '\n' {
                 self.line += 1;
             }
-            self.lex.push(c);
+            self.current += c.len_utf8();
         }
         result
     }
 
-    /// Advance `self.chars` if the following character satisfies the condition `func`.
+    /// Advance `self.current` if the following character satisfies the condition `func`.
     /// Returns the matched character.
     fn advance_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
-        // let result = self.chars.next_if(func);
-        // if let Some(c) = result {
-        //     if c == '\n' {
-        //         self.line += 1;
-        //     }
-        //     self.lex.push(c);
-        // }
-        // result
-
-        let result = self.chars.peek();
-        if let Some(&c) = result {
-            if func(&c) {
-                self.chars.next(); // advance
-                if c == '\n' {
-                    self.line += 1;
-                }
-                self.lex.push(c);
-                return Some(c);
-            }
+        match self.peek() {
+            Some(c) if func(&c) => self.advance(),
+            _ => None,
         }
-        None
     }
 
-    /// Advance `self.chars` if the following character matches to any candidates in `cand`.
+    /// Advance `self.current` if the following character matches to any candidates in `cand`.
     /// Returns the matched character.
     /// This is a shortcut of `self.advance_if`.
     #[inline]
@@ -76,32 +80,27 @@ impl<'a> Scanner<'a> {
         self.advance_if(|ch| cand.contains(ch))
     }
 
-    /// Keep advancing `self.chars` as long as the following character satisfies the condition `func`.
+    /// Keep advancing `self.current` as long as the following character satisfies the condition `func`.
     #[inline]
     fn advance_while(&mut self, func: impl Fn(&char) -> bool) {
         while self.advance_if(&func).is_some() { }
     }
 
-    /// Advance `self.chars` if the prefix exactly matches with `expected`.
+    /// Advance `self.current` if the prefix exactly matches with `expected`.
     /// returns whether the match succeeded.
-    /// **Note:** this method assumes that `expected` doesn't contain any newline characters.
-    fn advance_on_exact(&mut self, expected: &[char]) -> bool {
-        let expected_view: Vec<Option<char>> = expected.iter().map(|ch| Some(*ch)).collect();
-
-        if self.chars.peek_range(0, expected.len()) != &expected_view[..] {
+    /// Newlines in `expected` are counted into `self.line`.
+    fn advance_on_exact(&mut self, expected: &str) -> bool {
+        if !self.src[self.current..].starts_with(expected) {
             return false;
         }
 
-        let newline_cnt = expected.iter().filter(|&ch| *ch == '\n').count();
-
-        self.line += newline_cnt;
-        self.lex.extend_from_slice(expected);
-        self.chars.truncate_iterator_to_cursor();
+        self.line += expected.matches('\n').count();
+        self.current += expected.len();
 
         true
     }
 
-    /// advance `self.chars` until it reached to `target`.
+    /// advance `self.current` until it reached to `target`.
     /// if found, consume the target also.
     /// returns whether the target has been found.
     fn advance_until(&mut self, target: char) -> bool {
@@ -111,18 +110,152 @@ impl<'a> Scanner<'a> {
         self.advance().map(|c| c == target).unwrap_or(false)
     }
 
+    /// Keep advancing `self.current` over digits satisfying `is_digit`.
+    /// A single `_` is skipped as a digit separator, if it is followed by another digit.
+    fn advance_digits(&mut self, is_digit: impl Fn(&char) -> bool) {
+        loop {
+            if self.advance_if(&is_digit).is_some() {
+                continue;
+            }
+
+            let separator = matches!(self.rest(), [b'_', c, ..] if is_digit(&char::from(*c)));
+            if !separator {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    /// Scan the rest of a string literal after the opening `"`, or after the `}` closing an interpolation.
+    /// The lexeme keeps the wrapping `""` and raw escapes, while the literal holds the decoded contents.
+    /// The contents are borrowed from the source, unless they contain escape sequences.
+    ///
+    /// A string with interpolations is split into an `Interpolation` token for each part ending with `${`,
+    /// the tokens of each embedded expression, and a `String` token for the last part.
+    fn string(&mut self) -> Option<TokenResult<'a>> {
+        let body_start = self.current;
+        let mut decoded: Option<String> = None; // allocated on the first escape sequence.
+        let mut error = None; // the first invalid escape. keep scanning up to the closing `"` anyway.
+        let mut typ = TokenType::String;
+
+        let body_end = loop {
+            let offset = self.current;
+            match self.advance() {
+                None => return self.make_error("Unterminated string"),
+                Some('"') => break offset,
+                Some('$') if self.advance_on(&['{']).is_some() => {
+                    self.interpolations.push(0);
+                    typ = TokenType::Interpolation;
+                    break offset;
+                },
+                Some('\\') => {
+                    let line = self.line;
+                    let src = self.src;
+                    let buf = decoded.get_or_insert_with(|| src[body_start..offset].to_string());
+                    match self.escape() {
+                        Ok(c) => buf.push(c),
+                        Err(message) => { error.get_or_insert(Handler::error(message, line)); },
+                    }
+                },
+                Some(c) => if let Some(buf) = decoded.as_mut() {
+                    buf.push(c);
+                },
+            }
+        };
+
+        let contents = match decoded {
+            Some(buf) => Cow::Owned(buf),
+            None => Cow::Borrowed(&self.src[body_start..body_end]),
+        };
+        match error {
+            Some(handler) => Some(Err(handler)),
+            None => self.make_literal_token(typ, Literal::String(contents)),
+        }
+    }
+
+    /// Decode an escape sequence right after `\\`.
+    fn escape(&mut self) -> Result<char, &'static str> {
+        match self.advance() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('$') => Ok('$'),
+            Some('u') => {
+                // \u{XXXXXX}, with 1 to 6 hex digits
+                if self.advance_on(&['{']).is_none() {
+                    return Err("Invalid unicode escape");
+                }
+                let start = self.current;
+                self.advance_while(char::is_ascii_hexdigit);
+                let digits = &self.src[start..self.current];
+
+                if self.advance_on(&['}']).is_none() || digits.is_empty() || digits.len() > 6 {
+                    return Err("Invalid unicode escape");
+                }
+                u32::from_str_radix(digits, 16).ok()
+                    .and_then(char::from_u32)
+                    .ok_or("Invalid unicode escape")
+            },
+            _ => Err("Invalid escape sequence"),
+        }
+    }
+
+    /// Scan the rest of a number literal after its first digit `first`.
+    fn number(&mut self, first: char) -> Option<TokenResult<'a>> {
+        // hexadecimal integer
+        if first == '0' && self.advance_on(&['x', 'X']).is_some() {
+            let start = self.current;
+            self.advance_digits(char::is_ascii_hexdigit);
+            if self.current == start {
+                return self.make_error("Expect hex digits after '0x'");
+            }
+
+            let value = self.src[start..self.current].chars()
+                .filter_map(|c| c.to_digit(16))
+                .fold(0.0, |acc, d| acc * 16.0 + f64::from(d));
+            return self.make_literal_token(TokenType::Number, Literal::Number(value));
+        }
+
+        self.advance_digits(char::is_ascii_digit);
+        if self.advance_on(&['.']).is_some() {
+            // unlike original lox spec, `123.` is a valid literal.
+            self.advance_digits(char::is_ascii_digit);
+        }
+
+        // exponent, only if digits follow. otherwise `e` starts an identifier.
+        let exponent = matches!(
+            self.rest(),
+            [b'e' | b'E', b'0'..=b'9', ..] | [b'e' | b'E', b'+' | b'-', b'0'..=b'9', ..]
+        );
+        if exponent {
+            self.advance();
+            self.advance_on(&['+', '-']);
+            self.advance_digits(char::is_ascii_digit);
+        }
+
+        let lexeme = self.lexeme();
+        let value = if lexeme.contains('_') {
+            lexeme.replace('_', "").parse()
+        } else {
+            lexeme.parse()
+        };
+        let value = value.expect("scanned number literal should be a valid f64");
+        self.make_literal_token(TokenType::Number, Literal::Number(value))
+    }
+
     fn skip_whitespace(&mut self) {
         // skip whitespaces until comments
         // while let Some(c) = self.advance_on(&[' ', '\r', '\t', '\n']) {
-        while let Some(_) = self.advance_if(patt!(' ' | '\r' | '\t' | '\n')) {}
+        while self.advance_if(patt!(' ' | '\r' | '\t' | '\n')).is_some() {}
 
         // skip // comments
-        if self.advance_on_exact(&['/', '/']) {
+        if self.advance_on_exact("//") {
             self.advance_until('\n');
         }
 
         // skip /* comments */
-        if self.advance_on_exact(&['/', '*']) {
+        if self.advance_on_exact("/*") {
             loop {
                 // no * found until the end of file
                 if !self.advance_until('*') {
@@ -130,7 +263,7 @@ impl<'a> Scanner<'a> {
                 }
 
                 // */ found
-                if let Some(_) = self.advance_on(&['/']) {
+                if self.advance_on(&['/']).is_some() {
                     break;
                 }
                 // if it loops, then single * is found.
@@ -138,30 +271,28 @@ impl<'a> Scanner<'a> {
             }
         }
 
-        match self.chars.peek() {
-            Some(' ' | '\r' | '\t' | '\n' | '/') => {
-                // loop more to skip further whitespaces (and comments)
-                self.skip_whitespace();
-            },
-            _ => ()
+        let more = match self.rest() {
+            [b' ' | b'\r' | b'\t' | b'\n', ..] => true,
+            [b'/', b'/' | b'*', ..] => true,
+            _ => false, // a single '/' is a Slash token, not a comment.
         };
+        if more {
+            // loop more to skip further whitespaces (and comments)
+            self.skip_whitespace();
+        }
 
-        self.lex.clear();
+        self.start = self.current;
     }
 
-    fn make_token(&mut self, typ: TokenType) -> Option<TokenResult> {
-        let token = Token::new(
-            typ,
-            self.lex.iter().collect(),
-            self.line,
-        );
-
-        self.lex.clear();
+    fn make_token(&mut self, typ: TokenType) -> Option<TokenResult<'a>> {
+        Some(Ok(Token::new(typ, self.lexeme(), self.line)))
+    }
 
-        Some(Ok(token))
+    fn make_literal_token(&mut self, typ: TokenType, literal: Literal<'a>) -> Option<TokenResult<'a>> {
+        Some(Ok(Token::with_literal(typ, self.lexeme(), self.line, literal)))
     }
 
-    fn make_error(&mut self, message: &'static str) -> Option<TokenResult> {
+    fn make_error(&mut self, message: &'static str) -> Option<TokenResult<'a>> {
         Some(Err(
             Handler::error(
                 message,
@@ -172,7 +303,7 @@ impl<'a> Scanner<'a> {
 }
 
 impl<'a> Iterator for Scanner<'a> {
-    type Item = TokenResult;
+    type Item = TokenResult<'a>;
     fn next(&mut self) -> Option<Self::Item> {
         self.skip_whitespace();
 
@@ -181,15 +312,58 @@ impl<'a> Iterator for Scanner<'a> {
             match c {
                 '(' => self.make_token(TokenType::LParen),
                 ')' => self.make_token(TokenType::RParen),
-                '{' => self.make_token(TokenType::LBrace),
-                '}' => self.make_token(TokenType::RBrace),
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    self.make_token(TokenType::LBrace)
+                },
+                '}' => match self.interpolations.last_mut() {
+                    // the end of an embedded expression: the string goes on.
+                    Some(0) => {
+                        self.interpolations.pop();
+                        self.string()
+                    },
+                    Some(depth) => {
+                        *depth -= 1;
+                        self.make_token(TokenType::RBrace)
+                    },
+                    None => self.make_token(TokenType::RBrace),
+                },
+                '[' => self.make_token(TokenType::LBracket),
+                ']' => self.make_token(TokenType::RBracket),
                 ';' => self.make_token(TokenType::Semicolon),
+                ':' => self.make_token(TokenType::Colon),
                 ',' => self.make_token(TokenType::Comma),
                 '.' => self.make_token(TokenType::Dot),
-                '-' => self.make_token(TokenType::Minus),
-                '+' => self.make_token(TokenType::Plus),
-                '*' => self.make_token(TokenType::Star),
-                '/' => self.make_token(TokenType::Slash),
+                '-' => match self.advance_on(&['=', '-']) {
+                    Some('=') => self.make_token(TokenType::MinusEq),
+                    Some(_) => self.make_token(TokenType::MinusMinus),
+                    None => self.make_token(TokenType::Minus),
+                },
+                '+' => match self.advance_on(&['=', '+']) {
+                    Some('=') => self.make_token(TokenType::PlusEq),
+                    Some(_) => self.make_token(TokenType::PlusPlus),
+                    None => self.make_token(TokenType::Plus),
+                },
+                '*' => match self.advance_on(&['=', '*']) {
+                    Some('=') => self.make_token(TokenType::StarEq),
+                    Some(_) => self.make_token(TokenType::StarStar),
+                    None => self.make_token(TokenType::Star),
+                },
+                '?' => match self.advance_on(&['.', '?']) {
+                    Some('.') => self.make_token(TokenType::QuestionDot),
+                    Some(_) => self.make_token(TokenType::QuestionQuestion),
+                    None => self.make_token(TokenType::Question),
+                },
+                '/' => match self.advance_on(&['=']) {
+                    Some(_) => self.make_token(TokenType::SlashEq),
+                    None => self.make_token(TokenType::Slash),
+                },
+                '%' => match self.advance_on(&['=']) {
+                    Some(_) => self.make_token(TokenType::PercentEq),
+                    None => self.make_token(TokenType::Percent),
+                },
 
                 '!' => match self.advance_on(&['=']) {
                     Some(_) => self.make_token(TokenType::BangEq),
@@ -209,33 +383,26 @@ impl<'a> Iterator for Scanner<'a> {
                 },
 
                 // string literal
-                '"' => match self.advance_until('"') {
-                    true => self.make_token(TokenType::String), // this will contain wrapping ""
-                    false => self.make_error("Unterminated string")
-                },
+                '"' => self.string(),
                 // number literal
-                '0'..='9' => {
-                    self.advance_while(patt!('0'..='9'));
-                    if self.advance_on(&['.']).is_some() {
-                        // unlike original lox spec, `123.` is a valid literal. 
-                        // todo
-                        self.advance_while(patt!('0'..='9'));
-                    }
-                    self.make_token(TokenType::Number)
-                },
+                '0'..='9' => self.number(c),
                 // identifiers and keywords
                 'a'..='z' | 'A'..='Z' | '_' => {
                     self.advance_while(patt!('0'..='9' | 'a'..='z' | 'A'..='Z' | '_'));
 
-                    let typ = TokenType::identify(&self.lex);
+                    let typ = TokenType::identify(self.lexeme());
                     self.make_token(typ)
                 },
 
                 _ => self.make_error("Unexpected character"),
             }
+        } else if !self.interpolations.is_empty() {
+            self.interpolations.clear();
+            self.make_error("Unterminated interpolation")
         } else if self.line > 0 {
+            let eof = Handler::eof(self.line);
             self.line = 0; // next token will be None.
-            Some(Err(Handler::EOF))
+            Some(Err(eof))
         } else { None }
     }
 }
\ No newline at end of file
@@ -1,22 +1,29 @@
+use std::borrow::Cow;
 use std::fmt;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum TokenType {
     LParen, RParen,
     LBrace, RBrace,
-    Comma, Dot, Minus, Plus,
-    Semicolon, Slash, Star,
+    LBracket, RBracket,
+    Colon, Comma, Dot, Minus, Plus,
+    Semicolon, Slash, Star, Percent,
 
     Bang, BangEq,
     Eq, EqEq,
     Gt, GtEq,
     Lt, LtEq,
+    PlusEq, PlusPlus,
+    MinusEq, MinusMinus,
+    StarEq, StarStar,
+    SlashEq, PercentEq,
+    Question, QuestionDot, QuestionQuestion,
 
-    String, Number, Ident,
+    String, Interpolation, Number, Ident,
 
-    And, Else, False,
+    And, Break, Continue, Else, False,
     For, Fun, If, Nil, Or,
-    Print, Return, True, Var, While,
+    Print, Return, Throw, True, Var, While,
     // Class, Super, This,
 }
 
@@ -27,42 +34,72 @@ impl fmt::Display for TokenType {
 }
 
 impl TokenType {
-    pub fn identify(lex: &[char]) -> Self {
-        let lex_str: &str = &lex.iter().collect::<String>();
+    /// Identify a keyword from an identifier lexeme, without allocation.
+    /// This is a hand-written trie, like `identifierType()` in clox:
+    /// branch on the leading characters, and compare the remaining suffix at once.
+    pub fn identify(lex: &str) -> Self {
+        fn check(rest: &[u8], suffix: &[u8], typ: TokenType) -> TokenType {
+            if rest == suffix { typ } else { TokenType::Ident }
+        }
 
-        // todo: implement automaton match
-        // here keywords are few enough to brute-force
-        match lex_str {
-            "and" => Self::And,
-            // "class" => Self::Class,
-            "else" => Self::Else,
-            "false" => Self::False,
-            "for" => Self::For,
-            "fun" => Self::Fun,
-            "if" => Self::If,
-            "nil" => Self::Nil,
-            "or" => Self::Or,
-            "print" => Self::Print,
-            "return" => Self::Return,
-            // "super" => Self::Super,
-            // "this" => Self::This,
-            "true" => Self::True,
-            "var" => Self::Var,
-            "while" => Self::While,
+        match lex.as_bytes() {
+            [b'a', rest @ ..] => check(rest, b"nd", Self::And),
+            [b'b', rest @ ..] => check(rest, b"reak", Self::Break),
+            // [b'c', b'l', rest @ ..] => check(rest, b"ass", Self::Class),
+            [b'c', b'o', rest @ ..] => check(rest, b"ntinue", Self::Continue),
+            [b'e', rest @ ..] => check(rest, b"lse", Self::Else),
+            [b'f', b'a', rest @ ..] => check(rest, b"lse", Self::False),
+            [b'f', b'o', rest @ ..] => check(rest, b"r", Self::For),
+            [b'f', b'u', rest @ ..] => check(rest, b"n", Self::Fun),
+            [b'i', rest @ ..] => check(rest, b"f", Self::If),
+            [b'n', rest @ ..] => check(rest, b"il", Self::Nil),
+            [b'o', rest @ ..] => check(rest, b"r", Self::Or),
+            [b'p', rest @ ..] => check(rest, b"rint", Self::Print),
+            [b'r', rest @ ..] => check(rest, b"eturn", Self::Return),
+            // [b's', rest @ ..] => check(rest, b"uper", Self::Super),
+            [b't', b'h', b'r', rest @ ..] => check(rest, b"ow", Self::Throw),
+            // [b't', b'h', rest @ ..] => check(rest, b"is", Self::This),
+            [b't', b'r', rest @ ..] => check(rest, b"ue", Self::True),
+            [b'v', rest @ ..] => check(rest, b"ar", Self::Var),
+            [b'w', rest @ ..] => check(rest, b"hile", Self::While),
             _ => Self::Ident,
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
-pub struct Token {
+/// Decoded payload of a literal token.
+#[derive(Clone, PartialEq, Debug)] // numbers are f64s here, so no Eq
+pub enum Literal<'a> {
+    String(Cow<'a, str>), // contents without wrapping "", escape sequences processed. borrowed if there were none.
+                          // for `Interpolation`, the part of a string up to `${`.
+    Number(f64),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Token<'a> {
     typ: TokenType,
-    lexeme: String, // to reflect clox better, &'a str should be used... but this is more rust-ish and we're using UTF-8 anyway
+    lexeme: &'a str, // a slice of the source, like clox
     line: usize,
+    literal: Option<Literal<'a>>,
 }
-impl Token {
-    pub fn new(typ: TokenType, lexeme: String, line: usize) -> Self {
-        Token { typ, lexeme, line }
+impl<'a> Token<'a> {
+    pub fn new(typ: TokenType, lexeme: &'a str, line: usize) -> Self {
+        Token { typ, lexeme, line, literal: None }
+    }
+    pub fn with_literal(typ: TokenType, lexeme: &'a str, line: usize, literal: Literal<'a>) -> Self {
+        Token { typ, lexeme, line, literal: Some(literal) }
+    }
+    pub fn typ(&self) -> TokenType {
+        self.typ
+    }
+    pub fn lexeme(&self) -> &'a str {
+        self.lexeme
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn literal(&self) -> Option<&Literal<'a>> {
+        self.literal.as_ref()
     }
 }
 
@@ -72,15 +109,22 @@ pub enum Handler {
         message: &'static str,
         line: usize,
     },
-    EOF
+    EOF {
+        line: usize,
+    },
 }
 impl Handler {
     pub fn error(message: &'static str, line: usize) -> Self {
         Self::Error { message, line }
     }
-    pub fn eof() -> Self {
-        Self::EOF
+    pub fn eof(line: usize) -> Self {
+        Self::EOF { line }
+    }
+    pub fn line(&self) -> usize {
+        match self {
+            Self::Error { line, .. } | Self::EOF { line } => *line,
+        }
     }
 }
 
-pub type TokenResult = Result<Token, Handler>;
\ No newline at end of file
+pub type TokenResult<'a> = Result<Token<'a>, Handler>;
\ No newline at end of file
@@ -7,30 +7,43 @@ use std::cmp::{
 };
 use std::fmt;
 
-#[derive(/* Debug, */ Copy, Clone, PartialEq)] // numbers are f64s here, so no Eq
-pub enum Value {
-    Number(f64), // pub unnecessary here
-    Bool(bool),
-    Nil,
-}
-
+// `Value` has two interchangeable representations with the same public API:
+// a tagged enum (default), and a NaN-boxed `u64` behind the `nan-boxing` feature.
+// each of them implements construction (`From<f64>`, `From<bool>`, `From<()>`, `From<ObjRef>`),
+// inspection (`TryFrom<Value> for f64`, `From<Value> for bool`, `is_nil`, `as_obj`, `same_type`) and `PartialEq`.
+// everything else is built on top of these, below.
+
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged;
+#[cfg(not(feature = "nan-boxing"))]
+pub use tagged::Value;
+
+#[cfg(feature = "nan-boxing")]
+mod nanbox;
+#[cfg(feature = "nan-boxing")]
+pub use nanbox::Value;
+
+/// Objects are only printed as a handle here. Use `Heap::display` to print their contents.
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Number(num) => write!(f, "{:.3}", num),
-            Self::Bool(b) => write!(f, "{}", b),
-            Self::Nil => write!(f, "nil"),
+        if let Ok(num) = f64::try_from(*self) {
+            write!(f, "{}", num) // integral numbers are printed without fraction, like clox's "%g"
+        } else if let Some(obj) = self.as_obj() {
+            write!(f, "<obj {}>", obj.index())
+        } else if self.is_nil() {
+            write!(f, "nil")
+        } else {
+            write!(f, "{}", bool::from(*self))
         }
-        // write!(f, "{:.3?}", self)
     }
 }
 
 impl fmt::Debug for Value { // for stack display.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Number(num) => write!(f, "{:.3}", num),
-            Self::Bool(b) => write!(f, "{}", b),
-            Self::Nil => write!(f, "nil"),
+        if let Ok(num) = f64::try_from(*self) {
+            write!(f, "{:.3}", num)
+        } else {
+            write!(f, "{}", self)
         }
     }
 }
@@ -48,17 +61,18 @@ pub type ValueOpnResult = Result<Value, ValueOpnError>;
 // }
 
 
+#[allow(clippy::result_unit_err)] // operand type errors carry no payload yet
 impl Value {
     /// Method for Instr::Add
     pub fn checked_add(self, other: Self) -> ValueOpnResult {
         // want a try block like this:
         // try {
-        //     Self::Number(
+        //     Self::from(
         //         f64::try_from(self)? + f64::try_from(other)?
         //     )
         // }
         Ok(
-            Self::Number(
+            Self::from(
                 f64::try_from(self)? + f64::try_from(other)?
             )
         )
@@ -67,7 +81,7 @@ impl Value {
     /// Method for Instr::Subtract
     pub fn checked_sub(self, other: Self) -> ValueOpnResult {
         Ok(
-            Self::Number(
+            Self::from(
                 f64::try_from(self)? - f64::try_from(other)?
             )
         )
@@ -76,7 +90,7 @@ impl Value {
     /// Method for Instr::Multiply
     pub fn checked_mul(self, other: Self) -> ValueOpnResult {
         Ok(
-            Self::Number(
+            Self::from(
                 f64::try_from(self)? * f64::try_from(other)?
             )
         )
@@ -85,24 +99,88 @@ impl Value {
     /// Method for Instr::Divide
     pub fn checked_div(self, other: Self) -> ValueOpnResult {
         Ok(
-            Self::Number(
+            Self::from(
                 f64::try_from(self)? / f64::try_from(other)?
             )
         )
     }
 
+    /// Method for Instr::Modulo. The result has the sign of `self`, like C's `fmod`.
+    pub fn checked_rem(self, other: Self) -> ValueOpnResult {
+        Ok(
+            Self::from(
+                f64::try_from(self)? % f64::try_from(other)?
+            )
+        )
+    }
+
+    /// Method for Instr::Power
+    pub fn checked_pow(self, other: Self) -> ValueOpnResult {
+        Ok(
+            Self::from(
+                f64::try_from(self)?.powf(f64::try_from(other)?)
+            )
+        )
+    }
+
     /// Method for Instr::Negate
     pub fn checked_neg(self) -> ValueOpnResult {
         Ok(
-            Self::Number(
+            Self::from(
                 -f64::try_from(self)?
             )
         )
     }
 
+    /// Method for Instr::Greater
+    pub fn checked_gt(self, other: Self) -> ValueOpnResult {
+        Ok(
+            Self::from(
+                f64::try_from(self)? > f64::try_from(other)?
+            )
+        )
+    }
+
+    /// Method for Instr::Less
+    pub fn checked_lt(self, other: Self) -> ValueOpnResult {
+        Ok(
+            Self::from(
+                f64::try_from(self)? < f64::try_from(other)?
+            )
+        )
+    }
+
+    /// Method for Instr::GreaterEqual
+    pub fn checked_ge(self, other: Self) -> ValueOpnResult {
+        Ok(
+            Self::from(
+                f64::try_from(self)? >= f64::try_from(other)?
+            )
+        )
+    }
+
+    /// Method for Instr::LessEqual
+    pub fn checked_le(self, other: Self) -> ValueOpnResult {
+        Ok(
+            Self::from(
+                f64::try_from(self)? <= f64::try_from(other)?
+            )
+        )
+    }
+
+    /// Method for Instr::Equal in strict mode.
+    /// Values of different types can't be compared, except that anything can be compared with `nil`.
+    pub fn checked_eq(self, other: Self) -> ValueOpnResult {
+        if self.is_nil() || other.is_nil() || self.same_type(other) {
+            Ok(Self::from(self == other))
+        } else {
+            Err(())
+        }
+    }
+
     /// Method for Instr::And
     pub fn and(self, other: Self) -> Self {
-        Value::Bool(
+        Value::from(
             bool::from(self)
             && bool::from(other)
         )
@@ -110,7 +188,7 @@ impl Value {
 
     /// Method for Instr::Or
     pub fn or(self, other: Self) -> Self {
-        Value::Bool(
+        Value::from(
             bool::from(self)
             || bool::from(other)
         )
@@ -121,79 +199,22 @@ impl Value {
 impl Not for Value {
     type Output = Self;
     fn not(self) -> Self::Output {
-        Value::Bool(
+        Value::from(
             bool::from(self).not()
         )
     }
 }
 
-/// Trait for Instr::{Equal, NotEqual} -- PartialEq (already derived)
+// Trait for Instr::{Equal, NotEqual} -- PartialEq (implemented by each representation)
+// Values of different types are never equal, and there's no numeric coercion (`true != 1`).
+// Numbers follow IEEE 754, so `NaN != NaN` and `0 == -0`.
 
-/// Trait for Instr::{Greater, Less, /* GreaterEqual, LessEqual */}
+/// Numbers are ordered by IEEE 754, and values of any other types are unordered.
+/// Instr::{Greater, Less} use `checked_gt` and `checked_lt` instead, which report unordered types as errors.
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if let (Value::Number(a), Value::Number(b)) = (self, other) {
-            a.partial_cmp(b)
+        if let (Ok(a), Ok(b)) = (f64::try_from(*self), f64::try_from(*other)) {
+            a.partial_cmp(&b)
         } else { None }
     }
 }
-
-// Conversions
-
-impl From<()> for Value {
-    fn from(_: ()) -> Value {
-        Value::Nil
-    }
-}
-
-impl From<f64> for Value {
-    fn from(num: f64) -> Value {
-        Value::Number(num)
-    }
-}
-
-impl From<bool> for Value {
-    fn from(b: bool) -> Value {
-        Value::Bool(b)
-    }
-}
-
-// impl From<Value> for f64 {
-//     fn from(value: Value) -> f64 {
-//         match value {
-//             Value::Number(num) => num,
-//             Value::Bool(b) => if b { 1.0 } else { 0.0 },
-//             Value::Nil => 0.0,
-//         }
-//     }
-// }
-
-impl From<Value> for bool {
-    fn from(value: Value) -> bool {
-        match value {
-            Value::Number(num) => num != 0.0,
-            Value::Bool(b) => b,
-            Value::Nil => false,
-        }
-    }
-}
-
-impl TryFrom<Value> for f64 {
-    type Error = ();
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        match value {
-            Value::Number(num) => Ok(num),
-            _ => Err(()),
-        }
-    }
-}
-
-// impl TryFrom<Value> for bool {
-//     type Error = ();
-//     fn try_from(value: Value) -> Result<Self, Self::Error> {
-//         match value {
-//             Value::Bool(b) => Ok(b),
-//             _ => Err(()),
-//         }
-//     }
-// }
\ No newline at end of file
@@ -0,0 +1,150 @@
+// NaN boxing, as in chapter 30 of clox.
+//
+// a double is a NaN if all of its exponent bits are set and its mantissa is nonzero.
+// a *quiet* NaN also has the highest mantissa bit set, and real arithmetic never produces
+// a NaN with the next bit (Intel's "QNaN Floating-Point Indefinite") set as well.
+// so any value with all of `QNAN` set can't be a number, and the remaining bits are free for us:
+//
+// * nil / false / true : `QNAN` | a tag in the lowest two bits.
+// * objects            : `SIGN_BIT` | `QNAN` | a 32-bit `ObjRef` index, instead of clox's 48-bit pointer.
+// * numbers            : anything else.
+//
+// the bits are kept in an `f64` rather than a `u64`, so that numbers stay in floating-point registers
+// through a chain of arithmetic: only the tag tests move them to integer registers, off the critical path.
+// every tagged value is a quiet NaN, so no load or store quiets it and changes its bits.
+
+use crate::object::ObjRef;
+
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+const NIL: u64 = QNAN | TAG_NIL;
+const FALSE: u64 = QNAN | TAG_FALSE;
+const TRUE: u64 = QNAN | TAG_TRUE;
+
+#[derive(Copy, Clone)]
+pub struct Value(f64);
+
+impl Value {
+    #[inline]
+    fn from_bits(bits: u64) -> Self {
+        Value(f64::from_bits(bits))
+    }
+
+    #[inline]
+    fn bits(self) -> u64 {
+        self.0.to_bits()
+    }
+
+    #[inline]
+    fn is_number(self) -> bool {
+        self.bits() & QNAN != QNAN
+    }
+
+    #[inline]
+    fn is_bool(self) -> bool {
+        self.bits() | 1 == TRUE // FALSE and TRUE only differ in the lowest bit
+    }
+
+    #[inline]
+    fn is_obj(self) -> bool {
+        self.bits() & (QNAN | SIGN_BIT) == QNAN | SIGN_BIT
+    }
+
+    /// Out of line, so that `From<f64>` branches past it rather than
+    /// selecting between the two results on every arithmetic operation.
+    #[cold]
+    fn nan() -> Self {
+        Value(f64::NAN)
+    }
+
+    pub fn is_nil(self) -> bool {
+        self.bits() == NIL
+    }
+
+    pub fn as_obj(self) -> Option<ObjRef> {
+        if self.is_obj() {
+            Some(ObjRef::from_index(self.bits() as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Whether `self` and `other` are of the same type.
+    pub fn same_type(self, other: Self) -> bool {
+        (self.is_number() && other.is_number())
+        || (self.is_bool() && other.is_bool())
+        || (self.is_nil() && other.is_nil())
+        || (self.is_obj() && other.is_obj())
+    }
+}
+
+/// Numbers compare by IEEE 754 (`NaN != NaN`, `0 == -0`), and everything else by identity.
+impl PartialEq for Value {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        if self.is_number() && other.is_number() {
+            self.0 == other.0
+        } else {
+            self.bits() == other.bits()
+        }
+    }
+}
+
+// Conversions
+
+impl From<()> for Value {
+    fn from(_: ()) -> Value {
+        Value::from_bits(NIL)
+    }
+}
+
+impl From<f64> for Value {
+    #[inline]
+    fn from(num: f64) -> Value {
+        // arithmetic never produces a NaN with all of `QNAN` set, but `f64::from_bits` could.
+        // canonicalize every NaN, so that they can't collide with our tags:
+        // only NaNs can, and testing for them is cheaper than testing the bits.
+        if num.is_nan() {
+            return Value::nan();
+        }
+        Value(num)
+    }
+}
+
+impl From<bool> for Value {
+    #[inline]
+    fn from(b: bool) -> Value {
+        Value::from_bits(if b { TRUE } else { FALSE })
+    }
+}
+
+impl From<ObjRef> for Value {
+    fn from(obj: ObjRef) -> Value {
+        Value::from_bits(SIGN_BIT | QNAN | u64::from(obj.index()))
+    }
+}
+
+/// Truthiness: only `nil` and `false` are falsey. `0` and `NaN` are truthy.
+impl From<Value> for bool {
+    #[inline]
+    fn from(value: Value) -> bool {
+        value.bits() != NIL && value.bits() != FALSE
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ();
+    #[inline]
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if value.is_number() {
+            Ok(value.0)
+        } else {
+            Err(())
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::object::ObjRef;
+
+#[derive(Copy, Clone, PartialEq)] // numbers are f64s here, so no Eq
+pub enum Value {
+    Number(f64), // pub unnecessary here
+    Bool(bool),
+    Nil,
+    Obj(ObjRef),
+}
+
+impl Value {
+    pub fn is_nil(self) -> bool {
+        matches!(self, Self::Nil)
+    }
+
+    pub fn as_obj(self) -> Option<ObjRef> {
+        match self {
+            Self::Obj(obj) => Some(obj),
+            _ => None,
+        }
+    }
+
+    /// Whether `self` and `other` are of the same type.
+    pub fn same_type(self, other: Self) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+}
+
+// Conversions
+
+impl From<()> for Value {
+    fn from(_: ()) -> Value {
+        Value::Nil
+    }
+}
+
+impl From<f64> for Value {
+    fn from(num: f64) -> Value {
+        Value::Number(num)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<ObjRef> for Value {
+    fn from(obj: ObjRef) -> Value {
+        Value::Obj(obj)
+    }
+}
+
+// impl From<Value> for f64 {
+//     fn from(value: Value) -> f64 {
+//         match value {
+//             Value::Number(num) => num,
+//             Value::Bool(b) => if b { 1.0 } else { 0.0 },
+//             Value::Nil => 0.0,
+//         }
+//     }
+// }
+
+/// Truthiness: only `nil` and `false` are falsey. `0` and `NaN` are truthy.
+impl From<Value> for bool {
+    fn from(value: Value) -> bool {
+        match value {
+            Value::Number(_) | Value::Obj(_) => true,
+            Value::Bool(b) => b,
+            Value::Nil => false,
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ();
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(num) => Ok(num),
+            _ => Err(()),
+        }
+    }
+}
+
+// impl TryFrom<Value> for bool {
+//     type Error = ();
+//     fn try_from(value: Value) -> Result<Self, Self::Error> {
+//         match value {
+//             Value::Bool(b) => Ok(b),
+//             _ => Err(()),
+//         }
+//     }
+// }
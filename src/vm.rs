@@ -1,151 +1,462 @@
 use crate::chunk::Chunk;
 use crate::value::Value;
-use crate::instr::Instr;
+use crate::instr::OpPrefix;
+use crate::object::{Heap, Obj};
+use crate::native::{self, Method, NativeError};
 use crate::compiler::compile;
+use crate::optimizer::optimize;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// pop two operands, and push the result of `Value::$method`, or report `$message` as a runtime error.
+macro_rules! binary_op {
+    ($self:ident, $method:ident, $message:expr) => {{
+        let b = $self.stack_pop();
+        let a = $self.stack_pop();
+        match a.$method(b) {
+            Ok(val) => $self.stack_push(val)?,
+            _ => return $self.runtime_error($message),
+        }
+    }};
+}
+
+/// How many instructions are executed between two wall-clock / interrupt checks.
+/// `Instant::now()` is far too expensive to call on every dispatch.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Execution budgets for `VM::run` and `RegisterVM::run`. `None` means unlimited.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    /// maximum number of executed instructions (fuel).
+    pub max_instructions: Option<u64>,
+    /// wall-clock budget, measured from the start of `VM::run`.
+    pub timeout: Option<Duration>,
+    /// maximum number of values on `stack`, checked on every push.
+    /// The register VM checks the number of registers its code uses instead, before running it.
+    pub max_stack: Option<usize>,
+    /// reserved for the maximum number of call frames. There are no function calls yet,
+    /// so this is not enforced.
+    pub max_call_depth: Option<usize>,
+    /// maximum number of bytes owned by heap objects, checked on every allocation.
+    pub max_heap_bytes: Option<usize>,
+}
+
+/// The budgets consumed by one run, shared by both VMs.
+pub(crate) struct Meter {
+    deadline: Option<Instant>,
+    executed: u64,
+}
+
+impl Meter {
+    pub(crate) fn start(limits: &Limits) -> Self {
+        Meter { deadline: limits.timeout.map(|timeout| Instant::now() + timeout), executed: 0 }
+    }
+
+    /// count an instruction about to be executed, and check the budgets.
+    /// the interrupt flag and the clock are only polled once per `CHECK_INTERVAL` instructions.
+    #[inline]
+    pub(crate) fn tick(&mut self, limits: &Limits, interrupt: &AtomicBool) -> InterpretResult {
+        if self.executed.is_multiple_of(CHECK_INTERVAL) {
+            if interrupt.load(Ordering::Relaxed) {
+                return Err(InterpretError::Interrupted);
+            }
+            if matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+                return Err(InterpretError::Timeout);
+            }
+        }
+
+        // count the instruction before checking, so that `max_instructions: Some(n)` runs at most n.
+        self.executed += 1;
+        if matches!(limits.max_instructions, Some(max) if self.executed > max) {
+            return Err(InterpretError::OutOfFuel);
+        }
+        Ok(())
+    }
+}
 
 pub struct VM {
     chunk: Chunk,
     ip: usize, // original clox uses pointer ip. here we only use code index of the chunk
     stack: Vec<Value>,
+
+    limits: Limits,
+    interrupt: Arc<AtomicBool>,
+    heap: Heap,
+
+    strict: bool, // see `VM::set_strict`
+    optimize: bool, // see `VM::set_optimize`
 }
 
 impl VM {
     pub fn new(chunk: Chunk) -> Self {
+        Self::with_limits(chunk, Limits::default())
+    }
+
+    pub fn with_limits(chunk: Chunk, limits: Limits) -> Self {
+        let mut heap = Heap::new();
+        heap.set_limit(limits.max_heap_bytes);
         VM {
             chunk,
             ip: 0,
-            stack: Vec::new()
+            stack: Vec::new(),
+            limits,
+            interrupt: Arc::new(AtomicBool::new(false)),
+            heap,
+            strict: false,
+            optimize: false,
         }
     }
 
-    fn stack_push<V>(&mut self, val: V) where V: Into<Value> {
+    /// Run the peephole optimizer (`optimizer::optimize`) over compiled chunks in `VM::interpret`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// In strict mode, `==` and `!=` between values of different types (other than `nil`)
+    /// are runtime errors, instead of silently evaluating to not equal.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Returns a handle which aborts the running VM with `InterpretError::Interrupted` when set to `true`.
+    /// The flag is polled cooperatively, so it can be set from any other thread.
+    /// An interrupt raised before a run, e.g. while `VM::interpret` compiles, aborts the next run.
+    /// The flag is cleared when a run ends, so an interrupt only aborts one run.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupt)
+    }
+
+    fn stack_push<V>(&mut self, val: V) -> InterpretResult where V: Into<Value> {
+        if matches!(self.limits.max_stack, Some(max) if self.stack.len() >= max) {
+            return Err(InterpretError::StackOverflow);
+        }
         self.stack.push(val.into());
+        Ok(())
     }
 
     fn stack_pop(&mut self) -> Value {
-        self.stack.pop().unwrap_or(Value::Nil)
+        self.stack.pop().unwrap_or(Value::from(()))
+    }
+
+    /// collect garbage before an allocation, if the heap has grown enough.
+    /// everything reachable is on the stack.
+    fn maybe_collect(&mut self) {
+        if self.heap.should_collect() {
+            self.heap.collect(self.stack.iter().copied());
+        }
+    }
+
+    /// run the chunk from the beginning.
+    pub fn run(&mut self) -> InterpretResult {
+        let result = self.execute();
+        self.interrupt.store(false, Ordering::Relaxed);
+        result
     }
 
-    /// run the instruction.
-    fn run(&mut self) -> InterpretResult {
+    fn execute(&mut self) -> InterpretResult {
+        self.ip = 0;
+        self.stack.clear();
+        let mut meter = Meter::start(&self.limits);
+
         loop {
+            #[cfg(feature = "debug-trace-execution")]
+            self.trace();
+
             // Our VM is sequental: it just decode the next instruction at once.
             // real machines implement pipelining, and have different stages for fetching and decoding respectively.
-            let res = self.chunk.read(self.ip);
-            if res.is_none() {
+            // the hot loop dispatches on the opcode byte directly, and reads operands inline,
+            // rather than decoding each instruction into an `Instr` first.
+            let Some(byte) = self.read_byte() else {
                 return Ok(()); // code evaluated successfully
-            }
+            };
 
-            let (ires, len) = res.unwrap();
-            #[cfg(debug_assertions)]
-            {
-                println!("    {:.3?}", self.stack);
+            meter.tick(&self.limits, &self.interrupt)?;
 
-                // if self.ip should be a pointer, change this also
-                self.chunk.disasm(&ires, self.ip)
-            }
-            self.ip += len; // instr ptr proceeds
-
-            if let Ok(instr) = ires {
-                match instr {
-                    Instr::Constant { idx } => {
-                        let val = self.chunk.get_const(idx);
-                        self.stack_push(val);
-                    },
-                    Instr::Nil => {
-                        self.stack_push(());
-                    },
-                    Instr::True => {
-                        self.stack_push(true);
-                    },
-                    Instr::False => {
-                        self.stack_push(false);
-                    },
-                    Instr::Equal => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-
-                        self.stack_push(a == b); // PartialEq for Value
-                    },
-                    Instr::Greater => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-
-                        self.stack_push(a > b); // PartialOrd for Value
-                    },
-                    Instr::Less => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-
-                        self.stack_push(a < b); // PartialOrd for Value
-                    },
-
-                    // TODO: macros for here
-                    Instr::Add => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-                        match a.checked_add(b) {
-                            Ok(val) => self.stack_push(val),
-                            _ => return Err(InterpretError::RuntimeError) // TODO
-                        };
-                    },
-                    Instr::Subtract => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-                        match a.checked_sub(b) {
-                            Ok(val) => self.stack_push(val),
-                            _ => return Err(InterpretError::RuntimeError) // TODO
-                        };
-                    },
-                    Instr::Multiply => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-                        match a.checked_mul(b) {
-                            Ok(val) => self.stack_push(val),
-                            _ => return Err(InterpretError::RuntimeError) // TODO
-                        };
-                    },
-                    Instr::Divide => {
-                        let b = self.stack_pop();
-                        let a = self.stack_pop();
-                        match a.checked_div(b) {
-                            Ok(val) => self.stack_push(val),
-                            _ => return Err(InterpretError::RuntimeError) // TODO
-                        };
-                    },
-                    Instr::Negate => {
-                        let a = self.stack_pop();
-                        match a.checked_neg() {
-                            Ok(val) => self.stack_push(val),
-                            _ => return Err(InterpretError::RuntimeError) // TODO
+            match OpPrefix::from(byte) {
+                OpPrefix::CONSTANT => {
+                    let Some(idx) = self.read_byte() else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    let Some(val) = self.chunk.get_const(idx) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.stack_push(val)?;
+                },
+                OpPrefix::CONSTANT_0 | OpPrefix::CONSTANT_1 => {
+                    let idx = byte - u8::from(OpPrefix::CONSTANT_0);
+                    let Some(val) = self.chunk.get_const(idx) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.stack_push(val)?;
+                },
+                OpPrefix::NIL => {
+                    self.stack_push(())?;
+                },
+                OpPrefix::TRUE => {
+                    self.stack_push(true)?;
+                },
+                OpPrefix::FALSE => {
+                    self.stack_push(false)?;
+                },
+                OpPrefix::EQUAL | OpPrefix::NOT_EQUAL => {
+                    let b = self.stack_pop();
+                    let a = self.stack_pop();
+                    let eq = if self.strict {
+                        match native::strict_eq(&self.heap, a, b) {
+                            Ok(eq) => bool::from(eq),
+                            Err(err) => return self.native_error(err),
                         }
+                    } else {
+                        a == b // PartialEq for Value
+                    };
+                    self.stack_push(eq == (OpPrefix::from(byte) == OpPrefix::EQUAL))?;
+                },
+                OpPrefix::GREATER => binary_op!(self, checked_gt, "Operands must be numbers."),
+                OpPrefix::LESS => binary_op!(self, checked_lt, "Operands must be numbers."),
+                OpPrefix::GREATER_EQUAL => binary_op!(self, checked_ge, "Operands must be numbers."),
+                OpPrefix::LESS_EQUAL => binary_op!(self, checked_le, "Operands must be numbers."),
+                OpPrefix::ADD => {
+                    self.maybe_collect(); // while the operands are still on the stack
+                    let b = self.stack_pop();
+                    let a = self.stack_pop();
+                    match native::add(&mut self.heap, a, b) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::ADD_CONST => {
+                    let Some(idx) = self.read_byte() else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    let Some(b) = self.chunk.get_const(idx) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.maybe_collect();
+                    let a = self.stack_pop();
+                    match native::add(&mut self.heap, a, b) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::SUBTRACT => binary_op!(self, checked_sub, "Operands must be numbers."),
+                OpPrefix::MULTIPLY => binary_op!(self, checked_mul, "Operands must be numbers."),
+                OpPrefix::DIVIDE => binary_op!(self, checked_div, "Operands must be numbers."),
+                OpPrefix::MODULO => binary_op!(self, checked_rem, "Operands must be numbers."),
+                OpPrefix::POWER => binary_op!(self, checked_pow, "Operands must be numbers."),
+                OpPrefix::NOT => {
+                    let a = self.stack_pop();
+                    self.stack_push(!a)?;
+                },
+                OpPrefix::NEGATE => {
+                    let a = self.stack_pop();
+                    match a.checked_neg() {
+                        Ok(val) => self.stack_push(val)?,
+                        _ => return self.runtime_error("Operand must be a number."),
+                    }
+                },
+
+                OpPrefix::BUILD_LIST => {
+                    let Some(count) = self.read_byte() else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.maybe_collect();
+                    let items = self.stack.split_off(self.stack.len().saturating_sub(count.into()));
+                    let list = self.heap.alloc(Obj::List(items)).map_err(|_| InterpretError::HeapLimitExceeded)?;
+                    self.stack_push(list)?;
+                },
+                OpPrefix::STRING => {
+                    let Some(idx) = self.read_byte() else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.maybe_collect();
+                    let Some(chars) = self.chunk.get_string(idx) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    let string = self.heap.intern(chars).map_err(|_| InterpretError::HeapLimitExceeded)?;
+                    self.stack_push(string)?;
+                },
+                OpPrefix::BUILD_STRING => {
+                    let Some(count) = self.read_byte() else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.maybe_collect();
+                    let parts = self.stack.split_off(self.stack.len().saturating_sub(count.into()));
+                    match native::build_string(&mut self.heap, &parts) {
+                        Ok(string) => self.stack_push(string)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::BUILD_MAP => {
+                    let Some(count) = self.read_byte() else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.maybe_collect();
+                    let entries = self.stack.split_off(self.stack.len().saturating_sub(2 * usize::from(count)));
+                    match native::build_map(&mut self.heap, &entries) {
+                        Ok(map) => self.stack_push(map)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::INDEX_GET => {
+                    let index = self.stack_pop();
+                    let target = self.stack_pop();
+                    match native::index_get(&self.heap, target, index) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::INDEX_PEEK => {
+                    let [target, index] = match self.stack.len().checked_sub(2) {
+                        Some(len) => [self.stack[len], self.stack[len + 1]],
+                        None => return self.runtime_error("Bad instruction."),
+                    };
+                    match native::index_get(&self.heap, target, index) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::INDEX_POST_ADD => {
+                    let delta = self.stack_pop();
+                    let index = self.stack_pop();
+                    let target = self.stack_pop();
+                    match native::index_post_add(&mut self.heap, target, index, delta) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::INDEX_SET => {
+                    let val = self.stack_pop();
+                    let index = self.stack_pop();
+                    let target = self.stack_pop();
+                    match native::index_set(&mut self.heap, target, index, val) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+                OpPrefix::INVOKE => {
+                    let (Some(method), Some(argc)) = (self.read_byte(), self.read_byte()) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    let Ok(method) = Method::try_from(method) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    self.maybe_collect();
+                    let args = self.stack.split_off(self.stack.len().saturating_sub(argc.into()));
+                    let receiver = self.stack_pop();
+                    match native::invoke(&mut self.heap, method, receiver, &args) {
+                        Ok(val) => self.stack_push(val)?,
+                        Err(err) => return self.native_error(err),
+                    }
+                },
+
+                OpPrefix::POP => {
+                    self.stack_pop();
+                },
+                OpPrefix::JUMP | OpPrefix::JUMP_IF_FALSE | OpPrefix::JUMP_IF_NIL | OpPrefix::JUMP_IF_NOT_NIL => {
+                    let (Some(hi), Some(lo)) = (self.read_byte(), self.read_byte()) else {
+                        return self.runtime_error("Bad instruction.");
+                    };
+                    let top = self.stack.last().copied().unwrap_or(Value::from(()));
+                    let jump = match OpPrefix::from(byte) {
+                        OpPrefix::JUMP => true,
+                        OpPrefix::JUMP_IF_FALSE => !bool::from(top),
+                        OpPrefix::JUMP_IF_NIL => top.is_nil(),
+                        _ => !top.is_nil(),
+                    };
+                    if jump {
+                        self.ip += usize::from(u16::from_be_bytes([hi, lo]));
                     }
-                    
-                    Instr::Return => {
-                        let val = self.stack_pop();
-                        println!("RESULT: {}", val);
-                        return Ok(());
-                    },
-                }
-            } else { // else if let ?
-                return Err(InterpretError::RuntimeError);
+                },
+
+                OpPrefix::THROW => {
+                    let val = self.stack_pop();
+                    let message = format!("Uncaught exception: {}", self.heap.display(val));
+                    return self.runtime_error(&message);
+                },
+
+                OpPrefix::RETURN => {
+                    let val = self.stack_pop();
+                    println!("{}", self.heap.display(val));
+                    return Ok(());
+                },
+                OpPrefix::RETURN_NIL => {
+                    println!("{}", Value::from(()));
+                    return Ok(());
+                },
+                OpPrefix::UNKNOWN(_) => {
+                    return self.runtime_error("Bad instruction.");
+                },
             }
         }
     }
 
+    /// read the byte at `self.ip`, and advance `self.ip`.
+    #[inline]
+    fn read_byte(&mut self) -> Option<u8> {
+        let byte = *self.chunk.code.get(self.ip)?;
+        self.ip += 1;
+        Some(byte)
+    }
+
+    /// print the stack, and disassemble the instruction about to be executed.
+    /// this is the only place where the VM decodes an `Instr`.
+    #[cfg(feature = "debug-trace-execution")]
+    fn trace(&self) {
+        if let Some((ires, _)) = self.chunk.read(self.ip) {
+            println!("    {:.3?}", self.stack);
+
+            // if self.ip should be a pointer, change this also
+            self.chunk.disasm(&ires, self.ip)
+        }
+    }
+
+    /// report a runtime error at the instruction just executed, and reset the stack.
+    fn runtime_error(&mut self, message: &str) -> InterpretResult {
+        eprintln!("{}", message);
+
+        // `self.ip` already points past the failing instruction.
+        let line = self.chunk.line_of(self.ip - 1);
+        eprintln!("[line {}] in script", line);
+
+        self.stack.clear();
+        Err(InterpretError::RuntimeError)
+    }
+
+    /// report the failure of a native operation: a runtime error, or running out of heap.
+    fn native_error(&mut self, err: NativeError) -> InterpretResult {
+        match err {
+            NativeError::Message(message) => self.runtime_error(&message),
+            NativeError::HeapLimit => Err(InterpretError::HeapLimitExceeded),
+        }
+    }
+
     /// mere combination of `compiler::compile` and `vm::run`.
     /// compile source code `src` and run it immediately.
     pub fn interpret(&mut self, src: &str) -> InterpretResult {
-        if let Some(chunk) = compile(src) {
+        if let Some(mut chunk) = compile(src) {
+            if self.optimize {
+                optimize(&mut chunk);
+            }
             self.chunk = chunk;
             self.run()
         } else { Err(InterpretError::CompileError) }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InterpretError {
     CompileError,
     RuntimeError,
+
+    // budget violations, see `Limits`.
+    OutOfFuel,
+    Timeout,
+    StackOverflow,
+    CallDepthExceeded, // reserved, see `Limits::max_call_depth`
+    HeapLimitExceeded,
+    Interrupted,
 }
 pub type InterpretResult = Result<(), InterpretError>;
 
@@ -0,0 +1,127 @@
+use rlox::compiler::{compile, compile_unfolded};
+use rlox::instr::Instr;
+use rlox::value::Value;
+
+use Instr::*;
+
+fn instrs(src: &str) -> Vec<Instr> {
+    let chunk = compile(src).unwrap();
+    chunk.iter().map(|(ires, _)| ires.unwrap()).collect()
+}
+
+/// Compiles `src`, which should fold into a single constant, and returns its value.
+fn folded(src: &str) -> Value {
+    let chunk = compile(src).unwrap();
+    match &instrs(src)[..] {
+        [Constant { idx }, Return] => {
+            assert_eq!(chunk.const_count(), 1, "{:?} left unused constants", src);
+            chunk.get_const(*idx).unwrap()
+        },
+        [Nil, Return] => Value::from(()),
+        [True, Return] => Value::from(true),
+        [False, Return] => Value::from(false),
+        code => panic!("{:?} compiled into {:?}", src, code),
+    }
+}
+
+#[test]
+fn arithmetic_is_folded() {
+    assert_eq!(folded("1 + 2 * 3 - 4"), Value::from(3.0));
+    assert_eq!(folded("(1 + 2) * (3 - 4)"), Value::from(-3.0));
+    assert_eq!(folded("-(2 / 4)"), Value::from(-0.5));
+    assert_eq!(folded("1 / 0"), Value::from(f64::INFINITY));
+    assert!(f64::try_from(folded("0 / 0")).unwrap().is_nan());
+
+    assert_eq!(compile_unfolded("1 + 2 * 3 - 4").unwrap().iter().count(), 8);
+}
+
+#[test]
+fn comparisons_and_not_are_folded() {
+    let table = [
+        ("1 < 2", true),
+        ("2 <= 1", false),
+        ("1 != 1", false),
+        ("!nil", true),
+        ("!0", false),
+        ("nil == false", false),
+        ("true == true", true),
+        // NaN is unordered, as in IEEE 754.
+        ("0 / 0 >= 1", false),
+        ("0 / 0 == 0 / 0", false),
+    ];
+
+    for (src, value) in table {
+        assert_eq!(folded(src), Value::from(value), "folding {:?}", src);
+    }
+}
+
+#[test]
+fn runtime_errors_are_not_folded() {
+    assert_eq!(instrs("-nil"), [Nil, Negate, Return]);
+    assert_eq!(instrs("1 < true"), [Constant { idx: 0 }, True, Less, Return]);
+    assert_eq!(instrs("1 + 2 * false"), [Constant { idx: 0 }, Constant { idx: 1 }, False, Multiply, Add, Return]);
+
+    // equality of different types is an error in strict mode only
+    assert_eq!(instrs("1 == true"), [Constant { idx: 0 }, True, Equal, Return]);
+}
+
+#[test]
+fn folding_frees_the_constant_pool() {
+    let src = vec!["1"; 1000].join(" + ");
+    assert_eq!(folded(&src), Value::from(1000.0));
+    assert!(compile_unfolded(&src).is_none());
+}
+
+#[test]
+fn string_literals_are_shared() {
+    let chunk = compile(r#"["a", "b", "a"]"#).unwrap();
+    assert_eq!(instrs(r#"["a", "b", "a"]"#), [
+        String { idx: 0 },
+        String { idx: 1 },
+        String { idx: 0 },
+        BuildList { count: 3 },
+        Return,
+    ]);
+    assert_eq!(chunk.string_count(), 2);
+    assert_eq!(chunk.get_string(1), Some("b"));
+}
+
+#[test]
+fn branches_are_not_folded_into_what_follows() {
+    // the `2` is only one of the values reaching the `+`.
+    assert_eq!(instrs("(nil ? 1 : 2) + 3"), [
+        Nil,
+        JumpIfFalse { offset: 6 },
+        Pop,
+        Constant { idx: 0 },
+        Jump { offset: 3 },
+        Pop,
+        Constant { idx: 1 },
+        Constant { idx: 2 },
+        Add,
+        Return,
+    ]);
+}
+
+#[test]
+fn string_concatenation_is_folded() {
+    let chunk = compile(r#""a" + "b" + "c""#).unwrap();
+    assert_eq!(instrs(r#""a" + "b" + "c""#), [String { idx: 0 }, Return]);
+    assert_eq!(chunk.string_count(), 1);
+    assert_eq!(chunk.get_string(0), Some("abc"));
+
+    // a string shared with earlier code stays in the table.
+    assert_eq!(instrs(r#"["a", "a" + "b"]"#), [String { idx: 0 }, String { idx: 1 }, BuildList { count: 2 }, Return]);
+    assert_eq!(instrs(r#""a" + 1"#), [String { idx: 0 }, Constant { idx: 0 }, Add, Return]);
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    // the whole expression is one level, and each `(` or `-` another.
+    let nested = |depth: usize, open: &str, close: &str| format!("{}1{}", open.repeat(depth - 1), close.repeat(depth - 1));
+    assert_eq!(folded(&nested(256, "(", ")")), Value::from(1.0));
+    assert!(compile(&nested(257, "(", ")")).is_none());
+    assert!(compile_unfolded(&nested(256, "- ", "")).is_some());
+    assert!(compile(&nested(100_000, "- ", "")).is_none());
+    assert!(compile(&nested(100_000, "[", "]")).is_none());
+}
@@ -0,0 +1,144 @@
+//! Golden-file tests in the format of the Crafting Interpreters test suite.
+//!
+//! Every `.lox` file under `tests/` is run through the `rlox` binary, and its output is
+//! compared against the annotations in its comments:
+//!
+//! * `// expect: <output>` -- a line printed to stdout.
+//! * `// Error at '<lexeme>': <message>` (or `// [line N] Error ...`) -- a compile error,
+//!   reported on this line (or line N). The exit code should be 65.
+//! * `// expect runtime error: <message>` -- a runtime error raised on this line.
+//!   The exit code should be 70.
+//! * `// expect register error: <message>` -- under `--register` only, a translation error
+//!   reported on this line, instead of everything else. The exit code should be 65.
+//!
+//! Each file is run without and with `-O`, on both the stack and the register VM,
+//! so the optimizer and the register backend are differentially tested against the same expectations.
+//! Files under `tests/strict/` are run in strict mode (`--strict`).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Default)]
+struct Expectation {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+    register_error: Option<String>,
+}
+
+impl Expectation {
+    fn parse(source: &str) -> Self {
+        let mut expect = Self::default();
+
+        for (idx, text) in source.lines().enumerate() {
+            let line = idx + 1;
+            let Some(pos) = text.find("//") else { continue };
+            let comment = text[pos + 2..].trim();
+
+            if let Some(output) = comment.strip_prefix("expect: ") {
+                expect.output.push(output.to_string());
+            } else if let Some(message) = comment.strip_prefix("expect register error: ") {
+                expect.register_error = Some(format!("[line {}] Error: {}", line, message));
+            } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+                expect.runtime_error = Some((message.to_string(), line));
+            } else if comment.starts_with("Error") {
+                expect.compile_errors.push(format!("[line {}] {}", line, comment));
+            } else if comment.starts_with("[line ") && comment.contains("] Error") {
+                expect.compile_errors.push(comment.to_string());
+            }
+        }
+
+        expect
+    }
+
+    /// The expectation under `--register`, if it differs.
+    fn for_register(&self) -> Option<Self> {
+        let error = self.register_error.clone()?;
+        Some(Self { compile_errors: vec![error], ..Self::default() })
+    }
+
+    fn exit_code(&self) -> i32 {
+        if !self.compile_errors.is_empty() {
+            65
+        } else if self.runtime_error.is_some() {
+            70
+        } else {
+            0
+        }
+    }
+
+    /// Returns the list of mismatches between the expectation and an actual run.
+    fn check(&self, stdout: &str, stderr: &str, code: i32) -> Vec<String> {
+        let mut failures = vec![];
+
+        let output: Vec<&str> = stdout.lines().collect();
+        if output != self.output {
+            failures.push(format!("expected output {:?}, got {:?}", self.output, output));
+        }
+
+        let errors: Vec<&str> = stderr.lines().collect();
+        if let Some((message, line)) = &self.runtime_error {
+            let trace = format!("[line {}]", line);
+            if errors.first() != Some(&message.as_str()) {
+                failures.push(format!("expected runtime error {:?}, got {:?}", message, errors));
+            } else if !errors.get(1).is_some_and(|e| e.starts_with(&trace)) {
+                failures.push(format!("expected runtime error on {}, got {:?}", trace, errors));
+            }
+        } else if errors != self.compile_errors {
+            failures.push(format!("expected errors {:?}, got {:?}", self.compile_errors, errors));
+        }
+
+        if code != self.exit_code() {
+            failures.push(format!("expected exit code {}, got {}", self.exit_code(), code));
+        }
+
+        failures
+    }
+}
+
+fn collect_lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_lox_files(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let mut files = vec![];
+    collect_lox_files(&root, &mut files);
+    files.sort();
+    assert!(!files.is_empty(), "no .lox files found under {}", root.display());
+
+    let mut failures = vec![];
+    for path in &files {
+        let expect = Expectation::parse(&fs::read_to_string(path).unwrap());
+        let register_expect = expect.for_register();
+        let strict: &[&str] = if path.starts_with(root.join("strict")) { &["--strict"] } else { &[] };
+
+        for flags in [&[][..], &["-O"], &["--register"], &["-O", "--register"]] {
+            let result = Command::new(env!("CARGO_BIN_EXE_rlox")).args(strict).args(flags).arg(path).output().unwrap();
+
+            let stdout = String::from_utf8_lossy(&result.stdout);
+            let stderr = String::from_utf8_lossy(&result.stderr);
+            let code = result.status.code().unwrap_or(-1);
+
+            let expect = match &register_expect {
+                Some(register_expect) if flags.contains(&"--register") => register_expect,
+                _ => &expect,
+            };
+            for failure in expect.check(&stdout, &stderr, code) {
+                let name = path.strip_prefix(&root).unwrap().display();
+                failures.push(format!("{} {}: {}", name, flags.join(" "), failure));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{} of {} tests failed:\n{}", failures.len(), files.len(), failures.join("\n"));
+}
@@ -0,0 +1,151 @@
+use rlox::object::{Heap, HeapLimitExceeded, Key, Map, Obj};
+use rlox::native::{invoke, Method};
+use rlox::value::Value;
+
+fn list(heap: &mut Heap, items: &[Value]) -> Value {
+    Value::from(heap.alloc(Obj::List(items.to_vec())).unwrap())
+}
+
+#[test]
+fn unreachable_lists_are_collected() {
+    let mut heap = Heap::new();
+    let inner = list(&mut heap, &[Value::from(1.0)]);
+    let outer = list(&mut heap, &[inner]);
+    let garbage = list(&mut heap, &[Value::from(2.0)]);
+    let _ = list(&mut heap, &[garbage]);
+    assert_eq!(heap.len(), 4);
+
+    heap.collect([outer]);
+    assert_eq!(heap.len(), 2);
+    assert_eq!(heap.display(outer).to_string(), "[[1]]");
+
+    heap.collect([]);
+    assert!(heap.is_empty());
+    assert_eq!(heap.bytes(), 0);
+}
+
+#[test]
+fn slots_are_reused() {
+    let mut heap = Heap::new();
+    let a = list(&mut heap, &[]);
+    heap.collect([]);
+    let b = list(&mut heap, &[]);
+    assert_eq!(a, b);
+}
+
+#[test]
+fn cycles_are_collected_and_printed() {
+    let mut heap = Heap::new();
+    let a = list(&mut heap, &[]);
+    invoke(&mut heap, Method::Push, a, &[a]).unwrap();
+    assert_eq!(heap.display(a).to_string(), "[[...]]");
+
+    heap.collect([a]);
+    assert_eq!(heap.len(), 1);
+    heap.collect([]);
+    assert!(heap.is_empty());
+}
+
+#[test]
+fn lists_are_mutated_in_place() {
+    // without variables, a golden file can't print a list after calling a method on it.
+    let mut heap = Heap::new();
+    let a = list(&mut heap, &[Value::from(1.0), Value::from(3.0)]);
+    let num = Value::from;
+
+    assert_eq!(invoke(&mut heap, Method::Insert, a, &[num(1.0), num(2.0)]), Ok(Value::from(())));
+    assert_eq!(heap.display(a).to_string(), "[1, 2, 3]");
+    assert_eq!(invoke(&mut heap, Method::Insert, a, &[num(-3.0), num(0.0)]), Ok(Value::from(())));
+    assert_eq!(heap.display(a).to_string(), "[0, 1, 2, 3]");
+
+    assert_eq!(invoke(&mut heap, Method::Remove, a, &[num(0.0)]), Ok(num(0.0)));
+    assert_eq!(heap.display(a).to_string(), "[1, 2, 3]");
+    assert_eq!(invoke(&mut heap, Method::Remove, a, &[num(-1.0)]), Ok(num(3.0)));
+    assert_eq!(heap.display(a).to_string(), "[1, 2]");
+}
+
+#[test]
+fn growth_is_accounted() {
+    let mut heap = Heap::new();
+    let a = list(&mut heap, &[]);
+    let before = heap.bytes();
+    for i in 0..100 {
+        invoke(&mut heap, Method::Push, a, &[Value::from(i as f64)]).unwrap();
+    }
+    assert!(heap.bytes() >= before + 100 * std::mem::size_of::<Value>());
+}
+
+#[test]
+fn strings_are_interned_until_collected() {
+    let mut heap = Heap::new();
+    let a = heap.intern("abc").unwrap();
+    assert_eq!(heap.intern("abc").unwrap(), a);
+    assert_ne!(heap.intern("abd").unwrap(), a);
+
+    heap.collect([Value::from(a)]);
+    assert_eq!(heap.len(), 1);
+    assert_eq!(heap.intern("abc").unwrap(), a);
+
+    heap.collect([]);
+    assert!(heap.is_empty());
+    heap.intern("abc").unwrap(); // allocated again, instead of returning the freed handle
+    assert_eq!(heap.len(), 1);
+}
+
+#[test]
+fn maps_keep_insertion_order() {
+    let mut map = Map::default();
+    for i in 0..5 {
+        map.insert(Key::number(i as f64), Value::from(i as f64 * 10.0));
+    }
+    assert_eq!(map.remove(Key::number(1.0)), Some(Value::from(10.0)));
+    assert_eq!(map.remove(Key::number(1.0)), None);
+    map.insert(Key::number(1.0), Value::from(true));
+    map.insert(Key::number(3.0), Value::from(false));
+
+    let keys: Vec<_> = map.keys().map(|key| f64::try_from(key).unwrap()).collect();
+    assert_eq!(keys, [0.0, 2.0, 3.0, 4.0, 1.0]);
+    assert_eq!(map.get(Key::number(4.0)), Some(Value::from(40.0)));
+    assert_eq!(map.get(Key::number(3.0)), Some(Value::from(false)));
+}
+
+#[test]
+fn map_keys_and_values_are_traced() {
+    let mut heap = Heap::new();
+    let key = heap.intern("key").unwrap();
+    let value = list(&mut heap, &[]);
+    let mut map = Map::default();
+    map.insert(Key::String(key), value);
+    let map = Value::from(heap.alloc(Obj::Map(map)).unwrap());
+
+    heap.collect([map]);
+    assert_eq!(heap.len(), 3);
+    assert_eq!(heap.display(map).to_string(), "{\"key\": []}");
+}
+
+#[test]
+fn strings_are_quoted_inside_containers() {
+    let mut heap = Heap::new();
+    let chars = ["a, b", "say \"hi\"", "back\\slash", "new\nline", "${x} costs $5", "\u{7}"];
+    let strings: Vec<_> = chars.iter().map(|chars| Value::from(heap.intern(chars).unwrap())).collect();
+    let list = list(&mut heap, &strings);
+
+    assert_eq!(heap.display(strings[0]).to_string(), "a, b");
+    assert_eq!(
+        heap.display(list).to_string(),
+        r#"["a, b", "say \"hi\"", "back\\slash", "new\nline", "\${x} costs $5", "\u{7}"]"#,
+    );
+}
+
+#[test]
+fn allocations_past_the_limit_fail() {
+    let mut heap = Heap::new();
+    heap.set_limit(Some(256));
+    let small = heap.intern("small").unwrap();
+
+    let bytes = heap.bytes();
+    assert_eq!(heap.alloc(Obj::List(vec![Value::from(()); 100])).err(), Some(HeapLimitExceeded));
+    assert_eq!(heap.intern(&"a".repeat(300)).err(), Some(HeapLimitExceeded));
+    assert_eq!(heap.bytes(), bytes);
+    assert_eq!(heap.intern("small"), Ok(small));
+}
@@ -0,0 +1,183 @@
+use rlox::chunk::Chunk;
+use rlox::compiler::compile_unfolded;
+use rlox::instr::{Instr, OpPrefix};
+use rlox::native::Method;
+use rlox::optimizer::optimize;
+use rlox::value::Value;
+
+use Instr::*;
+
+/// Compiles `src` without folding, optimizes it, and returns the instructions with their lines.
+fn optimized(src: &str) -> (Vec<(Instr, usize)>, Chunk) {
+    let mut chunk = compile_unfolded(src).unwrap();
+    optimize(&mut chunk);
+    let instrs = chunk.iter().map(|(ires, offset)| (ires.unwrap(), chunk.line_of(offset))).collect();
+    (instrs, chunk)
+}
+
+fn instrs(src: &str) -> Vec<Instr> {
+    optimized(src).0.into_iter().map(|(instr, _)| instr).collect()
+}
+
+#[test]
+fn compare_and_not_is_fused() {
+    assert_eq!(instrs("1 != 2"), [Constant0, Constant1, NotEqual, Return]);
+    assert_eq!(instrs("1 >= 2"), [Constant0, Constant1, GreaterEqual, Return]);
+    assert_eq!(instrs("1 <= 2"), [Constant0, Constant1, LessEqual, Return]);
+    assert_eq!(instrs("!(1 != 2)"), [Constant0, Constant1, Equal, Return]);
+
+    // `!(a < b)` is true for NaN, where `a >= b` is false.
+    assert_eq!(instrs("!(1 < 2)"), [Constant0, Constant1, Less, Not, Return]);
+}
+
+#[test]
+fn literal_negation_and_not_are_folded() {
+    let (code, chunk) = optimized("-2");
+    assert_eq!(code, [(Constant1, 1), (Return, 1)]);
+    assert_eq!(chunk.get_const(1), Some(Value::from(-2.0)));
+
+    assert_eq!(instrs("!0"), [False, Return]);
+    assert_eq!(instrs("!nil"), [True, Return]);
+    assert_eq!(instrs("!!true"), [True, Return]);
+    assert_eq!(instrs("!!!(1 < 2)"), [Constant0, Constant1, Less, Not, Return]);
+}
+
+#[test]
+fn runtime_errors_are_not_folded() {
+    assert_eq!(instrs("-nil"), [Nil, Negate, Return]);
+    assert_eq!(instrs("-true"), [True, Negate, Return]);
+}
+
+#[test]
+fn lines_are_preserved() {
+    let (code, _) = optimized("1 <=\n\n2");
+    assert_eq!(code, [(Constant0, 1), (Constant1, 3), (LessEqual, 3), (Return, 3)]);
+}
+
+#[test]
+fn code_after_return_is_dropped() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::RETURN, 1);
+    chunk.write(OpPrefix::NIL, 2);
+    optimize(&mut chunk);
+
+    assert_eq!(chunk.code, [u8::from(OpPrefix::RETURN)]);
+}
+
+#[test]
+fn undecodable_chunks_are_left_alone() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::EQUAL, 1);
+    chunk.write(OpPrefix::NOT, 1);
+    chunk.write(0xFFu8, 1);
+    optimize(&mut chunk);
+
+    assert_eq!(chunk.code, [u8::from(OpPrefix::EQUAL), u8::from(OpPrefix::NOT), 0xFF]);
+}
+
+#[test]
+fn superinstructions() {
+    assert_eq!(instrs("1 + 2 + 3"), [Constant0, AddConst { idx: 1 }, AddConst { idx: 2 }, Return]);
+    assert_eq!(instrs("nil"), [ReturnNil]);
+
+    // `AddConst` fails where `Add` did.
+    let (code, _) = optimized("(nil\n) + (1\n\n)");
+    assert_eq!(code, [(Nil, 1), (AddConst { idx: 0 }, 4), (Return, 4)]);
+}
+
+#[test]
+fn jumps_are_retargeted() {
+    // `Constant0` and `Constant1` are shorter than the loads they replace.
+    assert_eq!(instrs("nil ? 1 : 2"), [
+        Nil,
+        JumpIfFalse { offset: 5 },
+        Pop,
+        Constant0,
+        Jump { offset: 2 },
+        Pop,
+        Constant1,
+        Return,
+    ]);
+}
+
+#[test]
+fn jump_targets_are_not_rewritten_with_the_code_before() {
+    // the `Negate` is also reached from the `Jump`, so it can't be folded into the `2`.
+    assert_eq!(instrs("-(nil ? 1 : 2)"), [
+        Nil,
+        JumpIfFalse { offset: 5 },
+        Pop,
+        Constant0,
+        Jump { offset: 2 },
+        Pop,
+        Constant1,
+        Negate,
+        Return,
+    ]);
+
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::JUMP, 1);
+    chunk.write(0u8, 1);
+    chunk.write(1u8, 1); // into the middle of the `Constant`
+    chunk.write_const(Value::from(1.0), 1);
+    let code = chunk.code.clone();
+    optimize(&mut chunk);
+    assert_eq!(chunk.code, code);
+}
+
+#[test]
+fn jumps_to_jumps_are_threaded() {
+    // the inner `Jump` goes to the outer one, and on to the end.
+    assert_eq!(instrs("nil ? (nil ? 1 : 2) : 3"), [
+        Nil,
+        JumpIfFalse { offset: 15 },
+        Pop,
+        Nil,
+        JumpIfFalse { offset: 5 },
+        Pop,
+        Constant0,
+        Jump { offset: 8 },
+        Pop,
+        Constant1,
+        Jump { offset: 3 },
+        Pop,
+        Constant { idx: 2 },
+        Return,
+    ]);
+
+    // a `nil` is still `nil` at the second test.
+    assert_eq!(instrs("nil?.len()?.len()"), [
+        Nil,
+        JumpIfNil { offset: 9 },
+        Invoke { method: Method::Len, argc: 0 },
+        JumpIfNil { offset: 3 },
+        Invoke { method: Method::Len, argc: 0 },
+        Return,
+    ]);
+
+    // a different test can't be threaded.
+    assert_eq!(instrs("(nil ?? 1)?.len()"), [
+        Nil,
+        JumpIfNotNil { offset: 2 },
+        Pop,
+        Constant0,
+        JumpIfNil { offset: 3 },
+        Invoke { method: Method::Len, argc: 0 },
+        Return,
+    ]);
+}
+
+#[test]
+fn dead_pushes_are_removed() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::TRUE, 1);
+    chunk.write_const(Value::from(1.0), 1);
+    chunk.write(OpPrefix::POP, 1);
+    chunk.write(OpPrefix::NIL, 2);
+    chunk.write(OpPrefix::POP, 2);
+    chunk.write(OpPrefix::RETURN, 3);
+    optimize(&mut chunk);
+
+    assert_eq!(chunk.code, [u8::from(OpPrefix::TRUE), u8::from(OpPrefix::RETURN)]);
+    assert_eq!(chunk.line_of(1), 3);
+}
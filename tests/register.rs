@@ -0,0 +1,193 @@
+use rlox::chunk::Chunk;
+use rlox::compiler::compile_unfolded;
+use rlox::instr::OpPrefix;
+use rlox::native::Method;
+use rlox::optimizer::optimize;
+use rlox::register::compiler::translate;
+use rlox::register::instr::{Instr, OpCode, Operand};
+use rlox::register::vm::RegisterVM;
+use rlox::register::RegChunk;
+use rlox::vm::{InterpretError, Limits};
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use Instr::*;
+use Operand::{Const, Reg};
+
+fn instrs(chunk: &RegChunk) -> Vec<Instr> {
+    chunk.iter().map(|(ires, _)| ires.unwrap()).collect()
+}
+
+fn translated(src: &str) -> RegChunk {
+    translate(&compile_unfolded(src).unwrap()).unwrap()
+}
+
+#[test]
+fn constants_are_operands() {
+    let chunk = translated("(1 + 2) * -(3 - 4)");
+    assert_eq!(instrs(&chunk), [
+        Add { dst: 0, a: Const(0), b: Const(1) },
+        Subtract { dst: 1, a: Const(2), b: Const(3) },
+        Negate { dst: 1, a: Reg(1) },
+        Multiply { dst: 0, a: Reg(0), b: Reg(1) },
+        Return { a: Reg(0) },
+    ]);
+    assert_eq!(chunk.registers(), 2);
+
+    // the stack code needs 9 instructions for the same expression
+    assert_eq!(compile_unfolded("(1 + 2) * -(3 - 4)").unwrap().iter().count(), 9);
+}
+
+#[test]
+fn literals_and_superinstructions() {
+    assert_eq!(instrs(&translated("!nil == true")), [
+        LoadNil { dst: 0 },
+        Not { dst: 0, a: Reg(0) },
+        LoadTrue { dst: 1 },
+        Equal { dst: 0, a: Reg(0), b: Reg(1) },
+        Return { a: Reg(0) },
+    ]);
+
+    let mut chunk = compile_unfolded("1 + 2 >= 3").unwrap();
+    optimize(&mut chunk); // Constant0, AddConst, GreaterEqual
+    assert_eq!(instrs(&translate(&chunk).unwrap()), [
+        Add { dst: 0, a: Const(0), b: Const(1) },
+        GreaterEqual { dst: 0, a: Reg(0), b: Const(2) },
+        Return { a: Reg(0) },
+    ]);
+}
+
+#[test]
+fn large_constant_indices_are_loaded() {
+    let src = (0..130).map(|i| i.to_string()).collect::<Vec<_>>().join(" + ");
+    let code = instrs(&translated(&src));
+    assert_eq!(code[126], Add { dst: 0, a: Reg(0), b: Const(127) });
+    assert_eq!(code[127], LoadConst { dst: 1, idx: 128 });
+    assert_eq!(code[128], Add { dst: 0, a: Reg(0), b: Reg(1) });
+}
+
+#[test]
+fn too_many_registers() {
+    // `**` is right-associative, so every left operand waits in a register.
+    let src = format!("{}1", "1 ** ".repeat(200));
+    assert!(translate(&compile_unfolded(&src).unwrap()).is_none());
+}
+
+#[test]
+fn lines_are_preserved() {
+    let chunk = translated("-true +\n\n1");
+    let lines: Vec<_> = chunk.iter().map(|(_, offset)| chunk.line_of(offset)).collect();
+    assert_eq!(lines, [1, 1, 3, 3]);
+
+    assert_eq!(RegisterVM::new(chunk).run(), Err(InterpretError::RuntimeError));
+}
+
+#[test]
+fn bad_code_is_a_runtime_error() {
+    let mut chunk = RegChunk::new();
+    chunk.write(OpCode::RETURN, 1); // without its operand
+    assert_eq!(RegisterVM::new(chunk).run(), Err(InterpretError::RuntimeError));
+
+    let mut chunk = RegChunk::new();
+    chunk.write(0xFFu8, 1);
+    assert_eq!(RegisterVM::new(chunk).run(), Err(InterpretError::RuntimeError));
+
+    // constants missing from the pool, loaded and used as an operand.
+    let mut chunk = RegChunk::new();
+    chunk.write_instr(LoadConst { dst: 0, idx: 7 }, 1);
+    assert_eq!(RegisterVM::new(chunk).run(), Err(InterpretError::RuntimeError));
+
+    let mut chunk = RegChunk::new();
+    chunk.write_instr(Return { a: Const(7) }, 1);
+    assert_eq!(RegisterVM::new(chunk).run(), Err(InterpretError::RuntimeError));
+}
+
+#[test]
+fn bad_stack_code_is_not_translated() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::ADD, 1);
+    assert!(translate(&chunk).is_none());
+}
+
+#[test]
+fn list_elements_are_loaded_into_consecutive_registers() {
+    assert_eq!(instrs(&translated("[1, true].len()")), [
+        LoadTrue { dst: 1 },
+        LoadConst { dst: 0, idx: 0 }, // only loaded when the list is built
+        BuildList { dst: 0, count: 2 },
+        Invoke { dst: 0, method: Method::Len, argc: 0 },
+        Return { a: Reg(0) },
+    ]);
+}
+
+#[test]
+fn values_are_materialized_before_jumps() {
+    assert_eq!(instrs(&translated("1 + (nil ?? 2)")), [
+        LoadNil { dst: 1 },
+        LoadConst { dst: 0, idx: 0 },
+        JumpIfNotNil { a: Reg(1), offset: 3 },
+        LoadConst { dst: 1, idx: 1 }, // at the target, where the jump left a register
+        Add { dst: 0, a: Reg(0), b: Reg(1) },
+        Return { a: Reg(0) },
+    ]);
+}
+
+#[test]
+fn bad_jumps_are_not_translated() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::JUMP, 1);
+    chunk.write(0u8, 1);
+    chunk.write(9u8, 1); // past the end
+    assert!(translate(&chunk).is_none());
+
+    // the `Return` is reached with the `nil` popped, or not
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::NIL, 1);
+    chunk.write(OpPrefix::JUMP_IF_FALSE, 1);
+    chunk.write(0u8, 1);
+    chunk.write(1u8, 1);
+    chunk.write(OpPrefix::POP, 1);
+    chunk.write(OpPrefix::RETURN, 1);
+    assert!(translate(&chunk).is_none());
+}
+
+#[test]
+fn instruction_limit() {
+    // LoadTrue, LoadConst, BuildList, Invoke, Return
+    let run = |max| RegisterVM::with_limits(translated("[1, true].len()"), Limits { max_instructions: Some(max), ..Limits::default() }).run();
+    assert_eq!(run(0), Err(InterpretError::OutOfFuel));
+    assert_eq!(run(4), Err(InterpretError::OutOfFuel));
+    assert_eq!(run(5), Ok(()));
+}
+
+#[test]
+fn timeout() {
+    let limits = Limits { timeout: Some(Duration::ZERO), ..Limits::default() };
+    assert_eq!(RegisterVM::with_limits(translated("1 + 2"), limits).run(), Err(InterpretError::Timeout));
+}
+
+#[test]
+fn stack_limit_bounds_registers() {
+    let run = |max| RegisterVM::with_limits(translated("[1, true].len()"), Limits { max_stack: Some(max), ..Limits::default() }).run();
+    assert_eq!(run(1), Err(InterpretError::StackOverflow));
+    assert_eq!(run(2), Ok(()));
+}
+
+#[test]
+fn heap_limit() {
+    let limits = Limits { max_heap_bytes: Some(1024), ..Limits::default() };
+    let mut vm = RegisterVM::with_limits(RegChunk::default(), limits);
+    assert_eq!(vm.interpret("[[], [], []]"), Ok(()));
+
+    let src = format!("[{}]", vec!["[0, 0, 0, 0, 0, 0, 0, 0]"; 32].join(", "));
+    assert_eq!(vm.interpret(&src), Err(InterpretError::HeapLimitExceeded));
+}
+
+#[test]
+fn interrupt_before_run() {
+    let mut vm = RegisterVM::new(translated("1 + 2"));
+    vm.interrupt_handle().store(true, Ordering::Relaxed);
+    assert_eq!(vm.run(), Err(InterpretError::Interrupted));
+    assert_eq!(vm.run(), Ok(()));
+}
@@ -0,0 +1,315 @@
+use std::borrow::Cow;
+
+use rlox::scanner::Scanner;
+use rlox::token::{Handler, Literal, Token, TokenResult, TokenType};
+
+use TokenType::*;
+
+fn scan(src: &str) -> Vec<TokenResult<'_>> {
+    Scanner::from_source(src).collect()
+}
+
+fn tok(typ: TokenType, lexeme: &str, line: usize) -> TokenResult<'_> {
+    Ok(Token::new(typ, lexeme, line))
+}
+
+fn num(lexeme: &str, value: f64, line: usize) -> TokenResult<'_> {
+    Ok(Token::with_literal(Number, lexeme, line, Literal::Number(value)))
+}
+
+fn string<'a>(lexeme: &'a str, contents: &'a str, line: usize) -> TokenResult<'a> {
+    Ok(Token::with_literal(String, lexeme, line, Literal::String(contents.into())))
+}
+
+/// Scans `src`, which should produce exactly one token, and returns its type and lexeme.
+fn single(src: &str) -> (TokenType, std::string::String) {
+    match &scan(src)[..] {
+        [Ok(token), Err(Handler::EOF { .. })] => (token.typ(), token.lexeme().to_string()),
+        tokens => panic!("{:?} scanned into {:?}", src, tokens),
+    }
+}
+
+#[test]
+fn every_token_type() {
+    let table = [
+        ("(", LParen), (")", RParen),
+        ("{", LBrace), ("}", RBrace),
+        ("[", LBracket), ("]", RBracket),
+        (":", Colon), (",", Comma), (".", Dot), ("-", Minus), ("+", Plus),
+        (";", Semicolon), ("/", Slash), ("*", Star), ("%", Percent),
+
+        ("!", Bang), ("!=", BangEq),
+        ("=", Eq), ("==", EqEq),
+        (">", Gt), (">=", GtEq),
+        ("<", Lt), ("<=", LtEq),
+        ("+=", PlusEq), ("++", PlusPlus),
+        ("-=", MinusEq), ("--", MinusMinus),
+        ("*=", StarEq), ("**", StarStar),
+        ("/=", SlashEq), ("%=", PercentEq),
+        ("?", Question), ("?.", QuestionDot), ("??", QuestionQuestion),
+
+        ("\"str\"", String), ("12.5", Number), ("ident", Ident),
+
+        ("and", And), ("break", Break), ("continue", Continue), ("else", Else), ("false", False),
+        ("for", For), ("fun", Fun), ("if", If), ("nil", Nil), ("or", Or),
+        ("print", Print), ("return", Return), ("throw", Throw), ("true", True), ("var", Var), ("while", While),
+    ];
+
+    for (src, typ) in table {
+        assert_eq!(single(src), (typ, src.to_string()), "scanning {:?}", src);
+    }
+}
+
+#[test]
+fn keyword_prefixes_are_identifiers() {
+    for src in ["an", "andy", "breaks", "classy", "cont", "fortune", "nil_", "_while", "var1", "True"] {
+        assert_eq!(single(src), (Ident, src.to_string()), "scanning {:?}", src);
+    }
+}
+
+#[test]
+fn two_char_operators_are_greedy() {
+    assert_eq!(scan("!==<=>"), vec![
+        tok(BangEq, "!=", 1),
+        tok(Eq, "=", 1),
+        tok(LtEq, "<=", 1),
+        tok(Gt, ">", 1),
+        Err(Handler::eof(1)),
+    ]);
+
+    assert_eq!(scan("---=**=+++"), vec![
+        tok(MinusMinus, "--", 1),
+        tok(MinusEq, "-=", 1),
+        tok(StarStar, "**", 1),
+        tok(Eq, "=", 1),
+        tok(PlusPlus, "++", 1),
+        tok(Plus, "+", 1),
+        Err(Handler::eof(1)),
+    ]);
+
+    assert_eq!(scan("???.?:"), vec![
+        tok(QuestionQuestion, "??", 1),
+        tok(QuestionDot, "?.", 1),
+        tok(Question, "?", 1),
+        tok(Colon, ":", 1),
+        Err(Handler::eof(1)),
+    ]);
+}
+
+#[test]
+fn numbers() {
+    let table = [
+        ("0", vec![num("0", 0.0, 1)]),
+        ("123.", vec![num("123.", 123.0, 1)]), // a valid literal, unlike the original lox spec
+        ("1.5.2", vec![num("1.5", 1.5, 1), tok(Dot, ".", 1), num("2", 2.0, 1)]),
+        (".5", vec![tok(Dot, ".", 1), num("5", 5.0, 1)]),
+        ("-3", vec![tok(Minus, "-", 1), num("3", 3.0, 1)]),
+    ];
+
+    for (src, mut expected) in table {
+        expected.push(Err(Handler::eof(1)));
+        assert_eq!(scan(src), expected, "scanning {:?}", src);
+    }
+}
+
+#[test]
+fn number_literals() {
+    let table = [
+        ("1_000_000", 1_000_000.0),
+        ("12.345_6", 12.3456),
+        ("0x1F", 31.0),
+        ("0Xff_ff", 65535.0),
+        ("1e3", 1000.0),
+        ("2.5E-2", 0.025),
+        ("1.e+2", 100.0),
+        ("0123", 123.0),
+    ];
+
+    for (src, value) in table {
+        assert_eq!(scan(src), vec![num(src, value, 1), Err(Handler::eof(1))], "scanning {:?}", src);
+    }
+}
+
+#[test]
+fn number_literal_boundaries() {
+    // a separator must be followed by a digit, and an exponent by (signed) digits
+    assert_eq!(scan("1_"), vec![num("1", 1.0, 1), tok(Ident, "_", 1), Err(Handler::eof(1))]);
+    assert_eq!(scan("1__0"), vec![num("1", 1.0, 1), tok(Ident, "__0", 1), Err(Handler::eof(1))]);
+    assert_eq!(scan("2e"), vec![num("2", 2.0, 1), tok(Ident, "e", 1), Err(Handler::eof(1))]);
+    assert_eq!(scan("2e+"), vec![num("2", 2.0, 1), tok(Ident, "e", 1), tok(Plus, "+", 1), Err(Handler::eof(1))]);
+    assert_eq!(scan("0x"), vec![Err(Handler::error("Expect hex digits after '0x'", 1)), Err(Handler::eof(1))]);
+    assert_eq!(scan("0xg"), vec![Err(Handler::error("Expect hex digits after '0x'", 1)), tok(Ident, "g", 1), Err(Handler::eof(1))]);
+}
+
+#[test]
+fn strings() {
+    assert_eq!(scan("\"\""), vec![string("\"\"", "", 1), Err(Handler::eof(1))]);
+    assert_eq!(scan("\"a // b\""), vec![string("\"a // b\"", "a // b", 1), Err(Handler::eof(1))]);
+
+    // multi-line strings are reported on their last line
+    assert_eq!(scan("\"a\nb\""), vec![string("\"a\nb\"", "a\nb", 2), Err(Handler::eof(2))]);
+}
+
+#[test]
+fn string_escapes() {
+    let table = [
+        (r#""a\nb""#, "a\nb"),
+        (r#""\t""#, "\t"),
+        (r#""say \"hi\"""#, "say \"hi\""),
+        (r#""back\\slash""#, "back\\slash"),
+        (r#""\u{41}\u{e9}\u{1F600}""#, "A\u{e9}\u{1F600}"),
+    ];
+
+    for (src, contents) in table {
+        assert_eq!(scan(src), vec![string(src, contents, 1), Err(Handler::eof(1))], "scanning {}", src);
+    }
+}
+
+#[test]
+fn invalid_string_escapes() {
+    let table = [
+        (r#""\q""#, "Invalid escape sequence"),
+        (r#""\u41""#, "Invalid unicode escape"),
+        (r#""\u{}""#, "Invalid unicode escape"),
+        (r#""\u{1234567}""#, "Invalid unicode escape"),
+        (r#""\u{D800}""#, "Invalid unicode escape"), // surrogates are not chars
+        (r#""\u{41""#, "Invalid unicode escape"),
+    ];
+
+    for (src, message) in table {
+        assert_eq!(scan(src), vec![Err(Handler::error(message, 1)), Err(Handler::eof(1))], "scanning {}", src);
+    }
+
+    // the error points at the line of the escape, and scanning resumes after the string
+    assert_eq!(scan("\"\n\\x\n\" 1"), vec![
+        Err(Handler::error("Invalid escape sequence", 2)),
+        num("1", 1.0, 3),
+        Err(Handler::eof(3)),
+    ]);
+}
+
+#[test]
+fn unterminated_string() {
+    assert_eq!(scan("\"abc\n"), vec![
+        Err(Handler::error("Unterminated string", 2)),
+        Err(Handler::eof(2)),
+    ]);
+}
+
+#[test]
+fn line_comments() {
+    assert_eq!(scan("// only a comment"), vec![Err(Handler::eof(1))]);
+    assert_eq!(scan("1 // one\n2"), vec![num("1", 1.0, 1), num("2", 2.0, 2), Err(Handler::eof(2))]);
+    assert_eq!(scan("1 / 2"), vec![num("1", 1.0, 1), tok(Slash, "/", 1), num("2", 2.0, 1), Err(Handler::eof(1))]);
+}
+
+#[test]
+fn block_comments() {
+    assert_eq!(scan("/**/1"), vec![num("1", 1.0, 1), Err(Handler::eof(1))]);
+    assert_eq!(scan("/* a\n * b\n */ 1"), vec![num("1", 1.0, 3), Err(Handler::eof(3))]);
+
+    // the opening `/*` can't be reused as the closing `*/`
+    assert_eq!(scan("/*/ 1 */ 2"), vec![num("2", 2.0, 1), Err(Handler::eof(1))]);
+
+    // block comments don't nest: the first `*/` closes the comment
+    assert_eq!(scan("/* /* */ */"), vec![tok(Star, "*", 1), tok(Slash, "/", 1), Err(Handler::eof(1))]);
+}
+
+#[test]
+fn unterminated_block_comment() {
+    assert_eq!(scan("1 /* never\nclosed *"), vec![num("1", 1.0, 1), Err(Handler::eof(2))]);
+}
+
+#[test]
+fn whitespace_and_lines() {
+    assert_eq!(scan("a\r\nb\tc\n\n d"), vec![
+        tok(Ident, "a", 1),
+        tok(Ident, "b", 2),
+        tok(Ident, "c", 2),
+        tok(Ident, "d", 4),
+        Err(Handler::eof(4)),
+    ]);
+}
+
+#[test]
+fn unicode() {
+    // non-ASCII characters are allowed inside strings only
+    assert_eq!(single("\"héllo, 世界\""), (String, "\"héllo, 世界\"".to_string()));
+    assert_eq!(scan("é1"), vec![
+        Err(Handler::error("Unexpected character", 1)),
+        num("1", 1.0, 1),
+        Err(Handler::eof(1)),
+    ]);
+}
+
+#[test]
+fn unexpected_character() {
+    assert_eq!(scan("@#"), vec![
+        Err(Handler::error("Unexpected character", 1)),
+        Err(Handler::error("Unexpected character", 1)),
+        Err(Handler::eof(1)),
+    ]);
+}
+
+#[test]
+fn eof_is_emitted_once() {
+    let mut scanner = Scanner::from_source("");
+    assert_eq!(scanner.next(), Some(Err(Handler::eof(1))));
+    assert_eq!(scanner.next(), None);
+    assert_eq!(scanner.next(), None);
+}
+
+#[test]
+fn lexemes_borrow_the_source() {
+    let src = std::string::String::from("plain \"no escapes\" \"one\\n escape\"");
+    let tokens = scan(&src);
+    let src_range = src.as_bytes().as_ptr_range();
+
+    for token in tokens.iter().flatten() {
+        assert!(src_range.contains(&token.lexeme().as_ptr()), "{:?} is not a slice of the source", token);
+    }
+
+    let contents: Vec<_> = tokens.iter().flatten().filter_map(Token::literal).collect();
+    assert!(matches!(contents[0], Literal::String(Cow::Borrowed("no escapes"))));
+    assert!(matches!(contents[1], Literal::String(Cow::Owned(s)) if s == "one\n escape"));
+}
+
+fn part<'a>(lexeme: &'a str, contents: &'a str, line: usize) -> TokenResult<'a> {
+    Ok(Token::with_literal(Interpolation, lexeme, line, Literal::String(contents.into())))
+}
+
+#[test]
+fn interpolations() {
+    assert_eq!(scan(r#""a${b}c${ {} }""#), vec![
+        part("\"a${", "a", 1),
+        tok(Ident, "b", 1),
+        part("}c${", "c", 1),
+        tok(LBrace, "{", 1),
+        tok(RBrace, "}", 1),
+        string("}\"", "", 1),
+        Err(Handler::eof(1)),
+    ]);
+
+    // strings nest inside interpolations
+    assert_eq!(scan(r#""${"${1}"}""#), vec![
+        part("\"${", "", 1),
+        part("\"${", "", 1),
+        num("1", 1.0, 1),
+        string("}\"", "", 1),
+        string("}\"", "", 1),
+        Err(Handler::eof(1)),
+    ]);
+
+    // `$` alone, or escaped, is not an interpolation
+    assert_eq!(scan(r#""$a \${b}""#), vec![string(r#""$a \${b}""#, "$a ${b}", 1), Err(Handler::eof(1))]);
+}
+
+#[test]
+fn unterminated_interpolation() {
+    assert_eq!(scan("\"a${b\n"), vec![
+        part("\"a${", "a", 1),
+        tok(Ident, "b", 1),
+        Err(Handler::error("Unterminated interpolation", 2)),
+        Err(Handler::eof(2)),
+    ]);
+}
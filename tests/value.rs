@@ -0,0 +1,119 @@
+use rlox::chunk::Chunk;
+use rlox::value::Value;
+use rlox::vm::{InterpretError, VM};
+
+const NAN: f64 = f64::NAN;
+
+fn nil() -> Value {
+    Value::from(())
+}
+
+#[test]
+fn only_nil_and_false_are_falsey() {
+    assert!(!bool::from(nil()));
+    assert!(!bool::from(Value::from(false)));
+
+    assert!(bool::from(Value::from(true)));
+    for num in [0.0, -0.0, 1.0, NAN, f64::INFINITY] {
+        assert!(bool::from(Value::from(num)), "{} should be truthy", num);
+    }
+}
+
+#[test]
+fn equality() {
+    assert_eq!(nil(), nil());
+    assert_eq!(Value::from(0.0), Value::from(-0.0));
+
+    // no coercion between types
+    assert_ne!(Value::from(true), Value::from(1.0));
+    assert_ne!(Value::from(false), Value::from(0.0));
+    assert_ne!(nil(), Value::from(false));
+    assert_ne!(nil(), Value::from(0.0));
+
+    // IEEE 754
+    assert_ne!(Value::from(NAN), Value::from(NAN));
+}
+
+#[test]
+fn ordering_requires_numbers() {
+    let one = Value::from(1.0);
+    let two = Value::from(2.0);
+    assert_eq!(one.checked_lt(two), Ok(Value::from(true)));
+    assert_eq!(one.checked_gt(two), Ok(Value::from(false)));
+
+    // NaN is unordered, but still a number
+    assert_eq!(Value::from(NAN).checked_lt(one), Ok(Value::from(false)));
+    assert_eq!(Value::from(NAN).checked_gt(one), Ok(Value::from(false)));
+
+    for other in [nil(), Value::from(true)] {
+        assert!(one.checked_lt(other).is_err());
+        assert!(other.checked_gt(one).is_err());
+        assert!(other.checked_lt(other).is_err());
+    }
+}
+
+#[test]
+fn remainder_and_power() {
+    let num = |n: f64| Value::from(n);
+    assert_eq!(num(-7.0).checked_rem(num(3.0)), Ok(num(-1.0)));
+    assert_eq!(num(7.5).checked_rem(num(-2.0)), Ok(num(1.5)));
+    assert_eq!(num(2.0).checked_pow(num(-1.0)), Ok(num(0.5)));
+    assert!(num(1.0).checked_rem(num(0.0)).is_ok_and(|v| f64::try_from(v).unwrap().is_nan()));
+
+    assert!(num(1.0).checked_rem(nil()).is_err());
+    assert!(Value::from(true).checked_pow(num(1.0)).is_err());
+}
+
+#[test]
+fn strict_equality() {
+    assert_eq!(Value::from(1.0).checked_eq(Value::from(1.0)), Ok(Value::from(true)));
+    assert_eq!(Value::from(true).checked_eq(Value::from(false)), Ok(Value::from(false)));
+
+    // anything can be compared with nil
+    assert_eq!(nil().checked_eq(nil()), Ok(Value::from(true)));
+    assert_eq!(Value::from(0.0).checked_eq(nil()), Ok(Value::from(false)));
+
+    assert!(Value::from(true).checked_eq(Value::from(1.0)).is_err());
+}
+
+#[test]
+fn strict_mode_vm() {
+    let mut vm = VM::new(Chunk::new());
+    assert_eq!(vm.interpret("true == 1"), Ok(()));
+
+    vm.set_strict(true);
+    assert_eq!(vm.interpret("true == 1"), Err(InterpretError::RuntimeError));
+    assert_eq!(vm.interpret("true != 1"), Err(InterpretError::RuntimeError));
+    assert_eq!(vm.interpret("nil == 1"), Ok(()));
+    assert_eq!(vm.interpret("1 == 1"), Ok(()));
+}
+
+#[test]
+fn display() {
+    assert_eq!(Value::from(3.0).to_string(), "3");
+    assert_eq!(Value::from(-1.5).to_string(), "-1.5");
+    assert_eq!(Value::from(true).to_string(), "true");
+    assert_eq!(nil().to_string(), "nil");
+}
+
+#[test]
+fn number_roundtrip() {
+    for num in [0.0, -0.0, 1.5, f64::MAX, f64::MIN_POSITIVE, f64::INFINITY, f64::NEG_INFINITY] {
+        assert_eq!(f64::try_from(Value::from(num)).map(f64::to_bits), Ok(num.to_bits()));
+    }
+    assert!(f64::try_from(Value::from(NAN)).unwrap().is_nan());
+    assert!(f64::try_from(Value::from(-NAN)).unwrap().is_nan());
+    // a NaN with the bits of `nil` under the NaN-boxed representation
+    let tagged = f64::from_bits(0x7ffc_0000_0000_0001);
+    assert!(f64::try_from(Value::from(tagged)).unwrap().is_nan());
+    assert!(!Value::from(tagged).is_nil());
+
+    assert!(f64::try_from(nil()).is_err());
+    assert!(f64::try_from(Value::from(false)).is_err());
+}
+
+#[cfg(feature = "nan-boxing")]
+#[test]
+fn nan_boxed_size() {
+    assert_eq!(std::mem::size_of::<Value>(), 8);
+}
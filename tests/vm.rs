@@ -0,0 +1,137 @@
+use rlox::chunk::Chunk;
+use rlox::instr::OpPrefix;
+use rlox::vm::{InterpretError, Limits, VM};
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// NIL, POP repeated `count` times, then RETURN_NIL.
+fn busy_chunk(count: usize) -> Chunk {
+    let mut chunk = Chunk::new();
+    for _ in 0..count {
+        chunk.write(OpPrefix::NIL, 1);
+        chunk.write(OpPrefix::POP, 1);
+    }
+    chunk.write(OpPrefix::RETURN_NIL, 1);
+    chunk
+}
+
+#[test]
+fn unknown_opcode_is_a_runtime_error() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::NIL, 1);
+    chunk.write(0xFFu8, 1);
+
+    assert_eq!(VM::new(chunk).run(), Err(InterpretError::RuntimeError));
+}
+
+#[test]
+fn missing_constant_is_a_runtime_error() {
+    for op in [OpPrefix::CONSTANT, OpPrefix::ADD_CONST] {
+        let mut chunk = Chunk::new();
+        chunk.write(OpPrefix::NIL, 1);
+        chunk.write(op, 1);
+        chunk.write(7u8, 1);
+        assert_eq!(VM::new(chunk).run(), Err(InterpretError::RuntimeError));
+    }
+
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::CONSTANT_1, 1);
+    assert_eq!(VM::new(chunk).run(), Err(InterpretError::RuntimeError));
+}
+
+#[test]
+fn truncated_operand_is_a_runtime_error() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpPrefix::CONSTANT, 1);
+
+    assert_eq!(VM::new(chunk).run(), Err(InterpretError::RuntimeError));
+}
+
+#[test]
+fn run_is_repeatable() {
+    let mut chunk = Chunk::new();
+    chunk.write_const(1.0.into(), 1);
+    chunk.write(OpPrefix::NEGATE, 1);
+
+    let mut vm = VM::new(chunk);
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.run(), Ok(()));
+}
+
+#[test]
+fn heap_limit() {
+    let limits = Limits { max_heap_bytes: Some(1024), ..Limits::default() };
+    let mut vm = VM::with_limits(Chunk::new(), limits);
+    assert_eq!(vm.interpret("[[], [], []]"), Ok(()));
+
+    let src = format!("[{}]", vec!["[0, 0, 0, 0, 0, 0, 0, 0]"; 32].join(", "));
+    assert_eq!(vm.interpret(&src), Err(InterpretError::HeapLimitExceeded));
+
+    // a single allocation past the limit, by a string concatenation.
+    let src = format!("\"{}\" + \"b\"", "a".repeat(1000));
+    assert_eq!(vm.interpret(&src), Err(InterpretError::HeapLimitExceeded));
+}
+
+#[test]
+fn instruction_limit() {
+    // NIL, POP, NIL, POP, RETURN_NIL
+    let run = |max| VM::with_limits(busy_chunk(2), Limits { max_instructions: Some(max), ..Limits::default() }).run();
+    assert_eq!(run(0), Err(InterpretError::OutOfFuel));
+    assert_eq!(run(4), Err(InterpretError::OutOfFuel));
+    assert_eq!(run(5), Ok(()));
+}
+
+#[test]
+fn timeout() {
+    let limits = Limits { timeout: Some(Duration::ZERO), ..Limits::default() };
+    assert_eq!(VM::with_limits(busy_chunk(1), limits).run(), Err(InterpretError::Timeout));
+
+    let limits = Limits { timeout: Some(Duration::from_secs(60)), ..Limits::default() };
+    assert_eq!(VM::with_limits(busy_chunk(1), limits).run(), Ok(()));
+}
+
+#[test]
+fn stack_limit() {
+    let mut chunk = Chunk::new();
+    for _ in 0..3 {
+        chunk.write(OpPrefix::NIL, 1);
+    }
+    chunk.write(OpPrefix::RETURN, 1);
+
+    let run = |max| VM::with_limits(chunk.clone(), Limits { max_stack: Some(max), ..Limits::default() }).run();
+    assert_eq!(run(2), Err(InterpretError::StackOverflow));
+    assert_eq!(run(3), Ok(()));
+}
+
+#[test]
+fn interrupt() {
+    let mut vm = VM::new(busy_chunk(1_000_000));
+    let handle = vm.interrupt_handle();
+    let done = Arc::new(AtomicBool::new(false));
+
+    // keep setting the flag, so that it is set while `run` is running.
+    let setter = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || while !done.load(Ordering::Relaxed) {
+            handle.store(true, Ordering::Relaxed);
+            thread::sleep(Duration::from_millis(1));
+        })
+    };
+    assert_eq!(vm.run(), Err(InterpretError::Interrupted));
+    done.store(true, Ordering::Relaxed);
+    setter.join().unwrap();
+}
+
+#[test]
+fn interrupt_before_run() {
+    // e.g. a watchdog firing while `interpret` compiles.
+    let mut vm = VM::new(busy_chunk(1));
+    vm.interrupt_handle().store(true, Ordering::Relaxed);
+    assert_eq!(vm.run(), Err(InterpretError::Interrupted));
+
+    // the interrupt ended with the run it aborted.
+    assert_eq!(vm.run(), Ok(()));
+}